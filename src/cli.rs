@@ -0,0 +1,262 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::renderer::crossterm::device_settings::ColourMode;
+use simplelog::LevelFilter;
+use thiserror::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub const DEFAULT_SAVE_PATH: &str = "save.json";
+pub const DEFAULT_SETTINGS_PATH: &str = "settings.json";
+pub const DEFAULT_LOG_PATH: &str = "roguelike.log";
+
+pub const USAGE: &str = "\
+Usage: roguelike [OPTIONS]
+
+Options:
+    --seed <SEED>          Seed the run with a fixed value instead of the current time
+    --config <PATH>        Settings file to load and save options to [default: settings.json]
+    --save <PATH>          Where the world is saved to [default: save.json]
+    --log-level <LEVEL>    One of off, error, warn, info, debug or trace [default: info]
+    --log-file <PATH>      Where the log is written. Old logs are kept beside it with a number
+                           appended [default: roguelike.log]
+    --replay <PATH>        Play back a recorded replay instead of reading input
+    --record <PATH>        Record every input to a replay, written when the game exits
+    --colour <MODE>        Colour mode of the renderer: auto, monochrome, limited, ansi256 or
+                           true-colour. Overrides the settings file for this run
+    --headless             Run without a terminal or renderer. Requires --replay
+    -h, --help             Print this message
+";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CliError {
+    #[error("unknown option {0}")]
+    UnknownOption(String),
+    #[error("{0} needs a value")]
+    MissingValue(String),
+    #[error("{option} does not take a value")]
+    UnexpectedValue {
+        option: String
+    },
+    #[error("invalid value \"{value}\" for {option}: {reason}")]
+    InvalidValue {
+        option: String,
+        value: String,
+        reason: String
+    },
+    #[error("{0} was given more than once")]
+    Repeated(String),
+    #[error("{first} can't be used with {second}")]
+    Conflict {
+        first: String,
+        second: String
+    },
+    #[error("{option} requires {required}")]
+    Requires {
+        option: String,
+        required: String
+    }
+}
+
+/// How the game was asked to run from the command line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub seed: Option<u64>,
+    pub settings_path: PathBuf,
+    pub save_path: PathBuf,
+    pub log_level: LevelFilter,
+    pub log_file: PathBuf,
+    pub replay: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub colour_mode: Option<ColourMode>,
+    pub headless: bool
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            seed: None,
+            settings_path: PathBuf::from(DEFAULT_SETTINGS_PATH),
+            save_path: PathBuf::from(DEFAULT_SAVE_PATH),
+            log_level: LevelFilter::Info,
+            log_file: PathBuf::from(DEFAULT_LOG_PATH),
+            replay: None,
+            record: None,
+            colour_mode: None,
+            headless: false
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Run(Options),
+    Help
+}
+
+/// Parse a value, turning the parse error into something we can show the user
+fn parse_value<T>(option: &str, value: String) -> Result<T, CliError> where
+    T: FromStr,
+    T::Err: ToString {
+    value.parse().map_err(|e: T::Err| CliError::InvalidValue {
+        option: option.to_string(),
+        reason: e.to_string(),
+        value
+    })
+}
+
+/// Set an option that can only be given once
+fn set_once<T>(option: &str, slot: &mut Option<T>, value: T) -> Result<(), CliError> {
+    if slot.is_some() {
+        return Err(CliError::Repeated(option.to_string()));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+/// Parse the arguments the program was started with, not including the program name.
+/// Options can be given as `--option value` or `--option=value`
+pub fn parse<I>(args: I) -> Result<Command, CliError> where
+    I: IntoIterator<Item = String> {
+    let mut seed = None;
+    let mut settings_path = None;
+    let mut save_path = None;
+    let mut log_level = None;
+    let mut log_file = None;
+    let mut replay = None;
+    let mut record = None;
+    let mut colour_mode = None;
+    let mut headless = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => (option.to_string(), Some(value.to_string())),
+            _ => (arg, None)
+        };
+
+        match option.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--headless" => {
+                if inline_value.is_some() {
+                    return Err(CliError::UnexpectedValue { option });
+                }
+                headless = true;
+            },
+            "--seed" | "--config" | "--save" | "--log-level" | "--log-file" | "--replay" | "--record" | "--colour" | "--color" => {
+                let value = match inline_value.or_else(|| args.next()) {
+                    Some(value) if !value.is_empty() => value,
+                    _ => return Err(CliError::MissingValue(option))
+                };
+
+                match option.as_str() {
+                    "--seed" => set_once(&option, &mut seed, parse_value(&option, value)?)?,
+                    "--config" => set_once(&option, &mut settings_path, PathBuf::from(value))?,
+                    "--save" => set_once(&option, &mut save_path, PathBuf::from(value))?,
+                    "--log-level" => set_once(&option, &mut log_level, parse_value(&option, value)?)?,
+                    "--log-file" => set_once(&option, &mut log_file, PathBuf::from(value))?,
+                    "--replay" => set_once(&option, &mut replay, PathBuf::from(value))?,
+                    "--record" => set_once(&option, &mut record, PathBuf::from(value))?,
+                    _ => set_once(&option, &mut colour_mode, parse_value(&option, value)?)?
+                }
+            },
+            _ => return Err(CliError::UnknownOption(option))
+        }
+    }
+
+    // A replay carries its own seed
+    if seed.is_some() && replay.is_some() {
+        return Err(CliError::Conflict { first: "--seed".to_string(), second: "--replay".to_string() });
+    }
+
+    // Without a terminal there is nothing to read input from
+    if headless && replay.is_none() {
+        return Err(CliError::Requires { option: "--headless".to_string(), required: "--replay".to_string() });
+    }
+
+    let defaults = Options::default();
+    Ok(Command::Run(Options {
+        seed,
+        settings_path: settings_path.unwrap_or(defaults.settings_path),
+        save_path: save_path.unwrap_or(defaults.save_path),
+        log_level: log_level.unwrap_or(defaults.log_level),
+        log_file: log_file.unwrap_or(defaults.log_file),
+        replay,
+        record,
+        colour_mode,
+        headless
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(args: &str) -> Result<Command, CliError> {
+        parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(parse_str(""), Ok(Command::Run(Options::default())));
+        assert_eq!(parse_str("--seed 4 -h"), Ok(Command::Help));
+        assert_eq!(parse_str("--help"), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_options() {
+        let options = parse_str(
+            "--seed 42 --config my_settings.json --save=world.json --log-level debug --log-file game.log --colour true-colour --record=run.json"
+        ).unwrap();
+        assert_eq!(options, Command::Run(Options {
+            seed: Some(42),
+            settings_path: PathBuf::from("my_settings.json"),
+            save_path: PathBuf::from("world.json"),
+            log_level: LevelFilter::Debug,
+            log_file: PathBuf::from("game.log"),
+            replay: None,
+            record: Some(PathBuf::from("run.json")),
+            colour_mode: Some(ColourMode::TrueColour),
+            headless: false
+        }));
+
+        let options = parse_str("--replay run.json --headless --color=16").unwrap();
+        assert_eq!(options, Command::Run(Options {
+            replay: Some(PathBuf::from("run.json")),
+            colour_mode: Some(ColourMode::Limited),
+            headless: true,
+            ..Options::default()
+        }));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_str("--fast"), Err(CliError::UnknownOption("--fast".to_string())));
+        assert_eq!(parse_str("game.json"), Err(CliError::UnknownOption("game.json".to_string())));
+        assert_eq!(parse_str("--seed"), Err(CliError::MissingValue("--seed".to_string())));
+        assert_eq!(parse_str("--save="), Err(CliError::MissingValue("--save".to_string())));
+        assert_eq!(parse_str("--seed 1 --seed 2"), Err(CliError::Repeated("--seed".to_string())));
+        assert_eq!(parse_str("--headless=yes"), Err(CliError::UnexpectedValue { option: "--headless".to_string() }));
+        assert!(matches!(parse_str("--seed -1"), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse_str("--log-level loud"), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse_str("--colour 3"), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse_str("--seed 1 --replay run.json"), Err(CliError::Conflict { .. })));
+        assert!(matches!(parse_str("--headless"), Err(CliError::Requires { .. })));
+    }
+}
@@ -16,9 +16,16 @@
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-pub mod fence;
+pub mod frame_pipeline;
 pub mod engine;
 pub mod input;
+pub mod replay;
+pub mod plugin;
+pub mod profiler;
+pub mod stats_overlay;
+pub mod terminal;
+pub mod logging;
+pub mod crash;
 pub mod ecs;
 pub mod math;
 pub mod containers;
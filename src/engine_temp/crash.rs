@@ -0,0 +1,159 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::engine_temp::replay::RecordedInput;
+use crate::engine_temp::terminal;
+use serde::Serialize;
+use thiserror::Error;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::fs::{ self, File };
+use std::io::{ BufWriter, Write };
+use std::panic::{ self, PanicHookInfo };
+use std::path::{ Path, PathBuf };
+use std::sync::{ Mutex, Once };
+use std::thread;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+static PANIC_HOOK: Once = Once::new();
+/// The details of the last panic, recorded by the hook so they can be picked up once the
+/// panic is caught
+static LAST_PANIC: Mutex<Option<PanicDetails>> = Mutex::new(None);
+
+#[derive(Error, Debug)]
+pub enum CrashError {
+    #[error("could not write crash report")]
+    Io(#[from] std::io::Error),
+    #[error("could not serialise crash report")]
+    Format(#[from] serde_json::Error)
+}
+
+/// What we know about a panic
+#[derive(Clone, Debug, Serialize)]
+pub struct PanicDetails {
+    pub message: String,
+    pub location: Option<String>,
+    pub thread: String,
+    pub backtrace: String
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+impl PanicDetails {
+    fn from_hook(info: &PanicHookInfo) -> PanicDetails {
+        PanicDetails {
+            message: payload_message(info.payload()),
+            location: info.location().map(|location| location.to_string()),
+            thread: thread::current().name().unwrap_or("unnamed").to_string(),
+            backtrace: Backtrace::force_capture().to_string()
+        }
+    }
+
+    /// The details of a caught panic. Uses what the hook recorded if it was installed,
+    /// otherwise only the message can be recovered from the payload
+    pub fn take(payload: &(dyn Any + Send)) -> PanicDetails {
+        let recorded = LAST_PANIC.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        recorded.unwrap_or_else(|| PanicDetails {
+            message: payload_message(payload),
+            location: None,
+            thread: thread::current().name().unwrap_or("unnamed").to_string(),
+            backtrace: String::new()
+        })
+    }
+}
+
+/// Record the details of every panic for crash reports. The terminal is restored before
+/// anything else so the panic message can be read
+pub fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            terminal::restore();
+            *LAST_PANIC.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(PanicDetails::from_hook(info));
+            previous_hook(info);
+        }));
+    });
+}
+
+/// What the game was doing when it crashed. The recent inputs and the ticks they were
+/// consumed on show what led up to it
+#[derive(Clone, Debug, Serialize)]
+pub struct CrashReport {
+    pub panic: PanicDetails,
+    pub seed: u64,
+    pub tick: u64,
+    /// Names of the states on the stack, from the bottom up
+    pub states: Vec<String>,
+    pub recent_inputs: Vec<RecordedInput>,
+    /// Whatever each state chose to add to the report, keyed by state name
+    pub state_data: BTreeMap<String, serde_json::Value>
+}
+
+impl CrashReport {
+    /// Write the report into a directory, creating it if needed. Returns the report's path
+    pub fn save<P>(&self, directory: P) -> Result<PathBuf, CrashError> where
+        P: AsRef<Path> {
+        fs::create_dir_all(&directory)?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        let path = directory.as_ref().join(format!("crash-{}-{}.json", time, self.tick));
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine_temp::input::Input;
+
+    #[test]
+    fn test_save() {
+        let directory = std::env::temp_dir().join(format!("roguelike_crash_{}", std::process::id()));
+        let report = CrashReport {
+            panic: PanicDetails::take(&"boom"),
+            seed: 5,
+            tick: 12,
+            states: vec!["game".to_string()],
+            recent_inputs: vec![RecordedInput { tick: 11, input: Input::CloseGame }],
+            state_data: BTreeMap::from([("game".to_string(), serde_json::json!({ "entities": 1 }))])
+        };
+
+        let path = report.save(&directory).unwrap();
+        let saved: serde_json::Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        assert_eq!(saved["panic"]["message"], "boom");
+        assert_eq!(saved["seed"], 5);
+        assert_eq!(saved["recent_inputs"][0]["tick"], 11);
+        assert_eq!(saved["state_data"]["game"]["entities"], 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -18,7 +18,7 @@
 use crate::engine_temp::containers::sparse_set::SparseSet;
 use crate::engine_temp::ecs::components::component::Component; 
 use crate::engine_temp::ecs::entity::Entity;
-use serde::Serialize;
+use serde::{ Serialize, Serializer, ser::SerializeMap };
 use std::fmt;
 
 pub struct ComponentManager<T> where 
@@ -59,3 +59,19 @@ impl<T> fmt::Debug for ComponentManager<T> where
         .finish_non_exhaustive()
     }
 }
+
+/// Serialised as a map of entity index to component
+impl<T> Serialize for ComponentManager<T> where
+    T: Component {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where
+        S: Serializer {
+        let elements = self.entity_component_set.get_all_elements();
+        let mut map = serializer.serialize_map(Some(elements.len()))?;
+        for element in elements {
+            if let Some(component) = self.entity_component_set.get(element) {
+                map.serialize_entry(&element, component)?;
+            }
+        }
+        map.end()
+    }
+}
@@ -21,8 +21,21 @@ use crate::engine_temp::ecs::component_manager::ComponentManager;
 use crate::engine_temp::ecs::components::{
     transform::TransformComponent,
 };
+use serde::{ Serialize, Serializer, ser::SerializeStruct };
+use thiserror::Error;
 use std::collections::HashMap;
 use std::fmt;
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use std::path::Path;
+
+#[derive(Error, Debug)]
+pub enum WorldSaveError {
+    #[error("could not access save file")]
+    Io(#[from] std::io::Error),
+    #[error("could not serialise world")]
+    Format(#[from] serde_json::Error)
+}
 
 pub struct WorldComponents {
     pub transforms: ComponentManager<TransformComponent>
@@ -73,6 +86,25 @@ impl World {
         S: Into<String> {
         self.entity_prefabs.insert(prefab.into(), on_create);
     }
+
+    /// Write every entity's components to a file. Prefabs are code and aren't saved
+    pub fn save<P>(&self, path: P) -> Result<(), WorldSaveError> where
+        P: AsRef<Path> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Serialize for World {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where
+        S: Serializer {
+        let mut world = serializer.serialize_struct("World", 2)?;
+        world.serialize_field("entity_count", &self.entity_count)?;
+        world.serialize_field("transforms", &self.components.transforms)?;
+        world.end()
+    }
 }
 
 impl fmt::Debug for World {
@@ -17,12 +17,29 @@
 */
 
 use crate::engine_temp::input::Input;
-use crate::engine_temp::fence::FenceRC;
+use crate::engine_temp::input::keymap::{ Keymap, KeymapPreset };
+use crate::engine_temp::frame_pipeline::FramePipeline;
+use crate::engine_temp::replay::{ Replay, ReplayPlayer, RecordedInput };
+use crate::engine_temp::crash::{ CrashReport, PanicDetails };
+use crate::engine_temp::plugin::Plugin;
+use crate::engine_temp::profiler::{ Profiler, FrameTimings };
+use crate::renderer::crossterm::command_buffer::CommandBuffer;
+use crate::renderer::palette::Palette;
 use std::sync::mpsc;
-use std::rc::Rc;
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic::{ self, AssertUnwindSafe };
 
 use crate::engine_temp::game::state_machine::StateMachine;
-use std::time::{ Instant, Duration };
+use crate::engine_temp::game::state::{ State, InputResult };
+use crate::engine_temp::game::context::{ EngineContext, Time };
+use crate::renderer::crossterm::render_engine::RenderEngine;
+use std::time::{ Instant, Duration, SystemTime, UNIX_EPOCH };
+
+/// The fastest simulated time can pass relative to real time
+const MAX_TIME_SCALE: f64 = 64.0;
+/// The longest the fixed update can be set to wait between updates
+pub const MAX_SIMULATION_RATE: Duration = Duration::from_secs(1);
 
 pub struct GameHandler {
     pub state_machine: StateMachine,
@@ -30,6 +47,10 @@ pub struct GameHandler {
     last_update: Instant,
     accumulator: Duration,
     simulation_rate: Duration, 
+    max_fixed_steps: u32,
+    time_scale: f64,
+    paused: bool,
+    queued_steps: u32,
     pub runtime: Duration
 }
 
@@ -40,39 +61,230 @@ impl GameHandler {
             last_update: Instant::now(),
             accumulator: Duration::new(0, 0),
             simulation_rate: Duration::from_secs_f64(1.0 / 60.0),
+            max_fixed_steps: 8,
+            time_scale: 1.0,
+            paused: false,
+            queued_steps: 0,
             runtime: Duration::new(0, 0)
         }
     }
 
-    fn tick(&mut self) {
+    /// Stop the fixed update from running. Variable updates still happen so states can
+    /// respond while paused
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Resume the fixed update. Time spent paused is not simulated
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.queued_steps = 0;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Run a single fixed update on the next tick. Only has an effect while paused
+    pub fn step(&mut self) {
+        if self.paused {
+            self.queued_steps += 1;
+        }
+    }
+
+    /// Scale how fast simulated time passes relative to real time. A scale of 0 will
+    /// stop the simulation without pausing it. Scales past `MAX_TIME_SCALE` are clamped
+    pub fn set_time_scale(&mut self, time_scale: f64) {
+        if !time_scale.is_finite() || time_scale < 0.0 {
+            log::warn!("Ignoring invalid time scale {}", time_scale);
+            return;
+        }
+        if time_scale > MAX_TIME_SCALE {
+            log::warn!("Clamping time scale {} to {}", time_scale, MAX_TIME_SCALE);
+        }
+        self.time_scale = time_scale.min(MAX_TIME_SCALE);
+    }
+
+    pub fn time_scale(&self) -> f64 {
+        self.time_scale
+    }
+
+    /// Set the fixed rate the simulation is updated at. Rates slower than
+    /// `MAX_SIMULATION_RATE` are clamped
+    pub fn set_simulation_rate(&mut self, simulation_rate: Duration) {
+        if simulation_rate.is_zero() {
+            log::warn!("Ignoring simulation rate of zero");
+            return;
+        }
+        if simulation_rate > MAX_SIMULATION_RATE {
+            log::warn!("Clamping simulation rate {:?} to {:?}", simulation_rate, MAX_SIMULATION_RATE);
+        }
+        self.simulation_rate = simulation_rate.min(MAX_SIMULATION_RATE);
+    }
+
+    pub fn simulation_rate(&self) -> Duration {
+        self.simulation_rate
+    }
+
+    /// Set the most fixed updates that can be ran in a single tick. Any time past
+    /// this is dropped so a long stall doesn't cause the simulation to spiral
+    pub fn set_max_fixed_steps(&mut self, max_fixed_steps: u32) {
+        self.max_fixed_steps = u32::max(max_fixed_steps, 1);
+    }
+
+    /// How far we are between the last fixed update and the next one, from 0 to 1
+    pub fn alpha(&self) -> f64 {
+        self.accumulator.as_secs_f64() / self.simulation_rate.as_secs_f64()
+    }
+
+    /// Measure how much time has passed since the last tick and advance by it
+    /// Returns true if simulation time had to be dropped
+    fn begin_tick(&mut self, engine: &mut EngineContext) -> bool {
         let now = Instant::now();
         let delta_time = now - self.last_update;
         self.last_update = now;
 
-        self.accumulator += delta_time;
-        self.runtime += delta_time;
+        self.advance_time(delta_time, engine)
+    }
+
+    /// Advance by a recorded amount of time instead of the time that really passed
+    fn begin_recorded_tick(&mut self, delta_time: Duration, engine: &mut EngineContext) -> bool {
+        self.last_update = Instant::now();
+        self.advance_time(delta_time, engine)
+    }
+
+    fn advance_time(&mut self, delta_time: Duration, engine: &mut EngineContext) -> bool {
+        // A recorded or stalled tick can be arbitrarily long, so anything that could overflow
+        // saturates instead. The accumulator is clamped straight after anyway
+        self.runtime = self.runtime.saturating_add(delta_time);
+
+        let max_accumulated = self.simulation_rate.checked_mul(self.max_fixed_steps).unwrap_or(Duration::MAX);
+        let mut dropped = false;
+        if !self.paused {
+            let scaled = Duration::try_from_secs_f64(delta_time.as_secs_f64() * self.time_scale).unwrap_or(Duration::MAX);
+            self.accumulator = self.accumulator.saturating_add(scaled);
+            if self.accumulator > max_accumulated {
+                log::debug!("Simulation is behind by {:?}, dropping time", self.accumulator - max_accumulated);
+                self.accumulator = max_accumulated;
+                dropped = true;
+            }
+        }
+
+        engine.time = Time {
+            runtime: self.runtime,
+            delta_time,
+            simulation_rate: self.simulation_rate,
+            time_scale: self.time_scale,
+            paused: self.paused,
+            tick: engine.time.tick
+        };
+        dropped
+    }
+
+    /// Update every state, recording how long each phase took
+    fn update(&mut self, engine: &EngineContext, timings: &mut FrameTimings) {
+        let update_start = Instant::now();
+        for input in engine.inputs.iter() {
+            let result = self.state_machine.handle_input(engine, input);
+            match input {
+                Input::CloseGame if result == InputResult::Ignored => engine.events.send(EngineEvent::Stop),
+                Input::Resize(columns, rows) => engine.events.send(EngineEvent::Resize(*columns, *rows)),
+                _ => {}
+            }
+        }
 
-        self.state_machine.pre_update();
-        self.state_machine.update();
+        self.state_machine.pre_update(engine);
+        self.state_machine.update(engine);
 
-        while self.accumulator >= self.simulation_rate {
-            self.accumulator -= self.simulation_rate;
-            self.state_machine.update_fixed(self.simulation_rate.as_secs_f64());
+        let fixed_start = Instant::now();
+        if self.paused {
+            while self.queued_steps > 0 {
+                self.queued_steps -= 1;
+                self.state_machine.update_fixed(engine, self.simulation_rate.as_secs_f64());
+                timings.fixed_steps += 1;
+            }
+        } else {
+            while self.accumulator >= self.simulation_rate {
+                self.accumulator -= self.simulation_rate;
+                self.state_machine.update_fixed(engine, self.simulation_rate.as_secs_f64());
+                timings.fixed_steps += 1;
+            }
         }
+        timings.update_fixed = fixed_start.elapsed();
 
-        self.state_machine.post_update();
+        self.state_machine.post_update(engine);
+        timings.update = update_start.elapsed() - timings.update_fixed;
     }
 }
 
+/// Creates a state to push. States don't need to be thread safe, so we send a way to
+/// make one instead
+pub type StateFactory = Box<dyn FnOnce() -> Box<dyn State> + Send>;
+
 /// Events that the manages the engine itself
 pub enum EngineEvent {
     Stop,
+    /// Stop running fixed updates
+    Pause,
+    /// Resume running fixed updates
+    Resume,
+    TogglePause,
+    /// Run a single fixed update while paused
+    Step,
+    /// Scale how fast simulated time passes
+    SetTimeScale(f64),
+    /// Change the fixed rate the simulation is updated at
+    SetSimulationRate(Duration),
+    /// Push the created state onto the state stack
+    PushState(StateFactory),
+    /// Pop the top state from the state stack
+    PopState,
+    /// Replace the top state with the created state
+    ReplaceState(StateFactory),
+    /// Ask every state to save
+    RequestSave,
+    /// Toggle the debug overlay of the plugin with the given name
+    ToggleDebugOverlay(String),
+    /// The display was resized to the given number of columns and rows
+    Resize(u16, u16),
+}
+
+impl fmt::Debug for EngineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineEvent::Stop => write!(f, "Stop"),
+            EngineEvent::Pause => write!(f, "Pause"),
+            EngineEvent::Resume => write!(f, "Resume"),
+            EngineEvent::TogglePause => write!(f, "TogglePause"),
+            EngineEvent::Step => write!(f, "Step"),
+            EngineEvent::SetTimeScale(time_scale) => write!(f, "SetTimeScale({})", time_scale),
+            EngineEvent::SetSimulationRate(rate) => write!(f, "SetSimulationRate({:?})", rate),
+            EngineEvent::PushState(_) => write!(f, "PushState"),
+            EngineEvent::PopState => write!(f, "PopState"),
+            EngineEvent::ReplaceState(_) => write!(f, "ReplaceState"),
+            EngineEvent::RequestSave => write!(f, "RequestSave"),
+            EngineEvent::ToggleDebugOverlay(name) => write!(f, "ToggleDebugOverlay({})", name),
+            EngineEvent::Resize(columns, rows) => write!(f, "Resize({}, {})", columns, rows),
+        }
+    }
+}
+
+/// Sends events to the engine. Publishers can be cloned and sent to other threads
+#[derive(Clone)]
+pub struct EngineEventPublisher(mpsc::Sender<EngineEvent>);
+
+impl EngineEventPublisher {
+    pub fn send(&self, event: EngineEvent) {
+        if let Err(e) = self.0.send(event) {
+            log::debug!("Engine is no longer listening for events, dropped {:?}", e.0);
+        }
+    }
 }
 
 /// A wrapper around a mpsc Reciever and Sender so we don't pollute the engine namespace
 pub struct EngineEventHandler {
     event_queue: mpsc::Receiver<EngineEvent>,
-    sender_base: Rc<mpsc::Sender<EngineEvent>>
+    sender_base: EngineEventPublisher
 }
 
 impl EngineEventHandler {
@@ -80,11 +292,11 @@ impl EngineEventHandler {
         let (send, recv) = mpsc::channel();
         EngineEventHandler {
             event_queue: recv,
-            sender_base: Rc::new(send)
+            sender_base: EngineEventPublisher(send)
         }
     }
 
-    pub fn get_publisher(&mut self) -> Rc<mpsc::Sender<EngineEvent>> {
+    pub fn get_publisher(&self) -> EngineEventPublisher {
         self.sender_base.clone()
     }
 
@@ -96,65 +308,642 @@ impl EngineEventHandler {
     }
 }
 
+/// How many frames the profiler keeps timings for
+const PROFILER_WINDOW: usize = 120;
+/// How many of the most recent inputs are kept for crash reports
+const RECENT_INPUTS: usize = 64;
+
+/// Where the engine receives its inputs from
+enum InputSource {
+    /// Inputs are received as they happen
+    Live(mpsc::Receiver<Input>),
+    /// Inputs are played back from a recording
+    Replay(ReplayPlayer)
+}
+
 /// The way the game is initialised and ran.
 ///
-/// The engine will setup a render fence and input queue, and will update the game state
-/// as needed. If initialised with a render fence, it will synchronise the game state
-/// with the renderer as needed
+/// The engine will setup a frame pipeline and input queue, and will update the game state
+/// as needed. If initialised with a frame pipeline, it will wait for the renderer before
+/// starting each frame so it never gets too far ahead
 pub struct Engine {
-    render_fence: Option<FenceRC>,
-    input_queue: mpsc::Receiver<Input>,
+    frame_pipeline: Option<FramePipeline>,
+    render_engine: Option<RenderEngine>,
+    input_source: InputSource,
+    plugins: Vec<Box<dyn Plugin>>,
     engine_event_handler: EngineEventHandler,
+    publisher: EngineEventPublisher,
+    keymap: Keymap,
+    profiler: Profiler,
+    palette: Palette,
+    frame_inputs: Vec<Input>,
+    recent_inputs: VecDeque<RecordedInput>,
     running: bool,
+    crashed: bool,
+    shut_down: bool,
+    stop_after_replay: bool,
+    seed: u64,
+    tick: u64,
     pub game_handler: GameHandler
 }
 
 impl Engine {
-    pub fn new(input_queue: mpsc::Receiver<Input>, render_fence: Option<FenceRC>) -> Engine {
+    pub fn new(input_queue: mpsc::Receiver<Input>, frame_pipeline: Option<FramePipeline>) -> Engine {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or_default();
+        Engine::create(InputSource::Live(input_queue), seed, frame_pipeline)
+    }
+
+    /// Create an engine that plays back the inputs of a replay instead of live input
+    pub fn from_replay(replay: Replay, frame_pipeline: Option<FramePipeline>) -> Engine {
+        let seed = replay.seed;
+        Engine::create(InputSource::Replay(ReplayPlayer::new(replay)), seed, frame_pipeline)
+    }
+
+    fn create(input_source: InputSource, seed: u64, frame_pipeline: Option<FramePipeline>) -> Engine {
         let engine_event_handler = EngineEventHandler::new();
 
         Engine {
-            render_fence,
-            input_queue,
+            frame_pipeline,
+            render_engine: None,
+            input_source,
+            plugins: Vec::new(),
+            publisher: engine_event_handler.get_publisher(),
             engine_event_handler,
+            keymap: Keymap::preset(KeymapPreset::ViKeys),
+            profiler: Profiler::new(PROFILER_WINDOW),
+            palette: Palette::standard(),
+            frame_inputs: Vec::new(),
+            recent_inputs: VecDeque::with_capacity(RECENT_INPUTS),
             running: true,
+            crashed: false,
+            shut_down: false,
+            stop_after_replay: false,
+            seed,
+            tick: 0,
             game_handler: GameHandler::new()
         }
     }
 
+    /// Register a plugin. Plugins are ran in the order they are registered
+    pub fn with_plugin(mut self, plugin: Box<dyn Plugin>) -> Engine {
+        log::debug!("Registered plugin {}", plugin.name());
+        self.plugins.push(plugin);
+        self
+    }
+
+    /// Use the given seed for this run. Has no effect when playing back a replay
+    pub fn set_seed(&mut self, seed: u64) {
+        if let InputSource::Replay(_) = self.input_source {
+            log::warn!("Cannot change the seed of a replay");
+            return;
+        }
+        self.seed = seed;
+    }
+
+    /// Stop the engine once every input and tick of the replay being played back has ran
+    pub fn set_stop_after_replay(&mut self, stop_after_replay: bool) {
+        self.stop_after_replay = stop_after_replay;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Draw every visible state through the given renderer each tick
+    pub fn set_render_engine(&mut self, render_engine: RenderEngine) {
+        self.render_engine = Some(render_engine);
+    }
+
+    /// Set the bindings used to translate key presses into actions
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// Set the named colours states draw with
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    fn next_input(&mut self) -> Option<Input> {
+        match &mut self.input_source {
+            InputSource::Live(input_queue) => input_queue.try_recv().ok(),
+            InputSource::Replay(player) => player.next(self.tick)
+        }
+    }
+
     fn handle_input(&mut self) {
-        while let Ok(input) = self.input_queue.try_recv() {
-            match input {
-                Input::CloseGame => self.running = false 
+        self.frame_inputs.clear();
+        while let Some(input) = self.next_input() {
+            if self.recent_inputs.len() == RECENT_INPUTS {
+                self.recent_inputs.pop_front();
             }
+            self.recent_inputs.push_back(RecordedInput { tick: self.tick, input: input.clone() });
+            self.frame_inputs.push(input);
         }
     }
 
     fn handle_engine_events(&mut self) {
         while let Some(event) = self.engine_event_handler.pop() {
+            self.notify_plugins(|plugin, context| plugin.on_engine_event(context, &event));
+
             match event {
                 EngineEvent::Stop => self.running = false,
+                EngineEvent::Pause => self.game_handler.pause(),
+                EngineEvent::Resume => self.game_handler.resume(),
+                EngineEvent::TogglePause => {
+                    if self.game_handler.is_paused() {
+                        self.game_handler.resume()
+                    } else {
+                        self.game_handler.pause()
+                    }
+                },
+                EngineEvent::Step => self.game_handler.step(),
+                EngineEvent::SetTimeScale(time_scale) => self.game_handler.set_time_scale(time_scale),
+                EngineEvent::SetSimulationRate(rate) => self.game_handler.set_simulation_rate(rate),
+                EngineEvent::PushState(create_state) => self.game_handler.state_machine.queue_push(create_state()),
+                EngineEvent::PopState => self.game_handler.state_machine.queue_pop(),
+                EngineEvent::ReplaceState(create_state) => self.game_handler.state_machine.queue_replace(create_state()),
+                EngineEvent::RequestSave => {
+                    let context = EngineContext {
+                        events: &self.publisher,
+                        keymap: &self.keymap,
+                        inputs: &[],
+                        seed: self.seed,
+                        profiler: &self.profiler,
+                        palette: &self.palette,
+                        time: self.current_time()
+                    };
+                    self.game_handler.state_machine.save(&context);
+                },
+                EngineEvent::Resize(columns, rows) => {
+                    if let Some(render_engine) = &mut self.render_engine {
+                        render_engine.resize(columns, rows);
+                    }
+                },
+                // Only plugins care about overlays
+                EngineEvent::ToggleDebugOverlay(_) => {}
             }
         }
     }
 
     fn update_state(&mut self) {
-        self.game_handler.tick();
+        // Replays advance by the time each tick took when recorded, so the same number of
+        // fixed updates run between the same inputs
+        let recorded_delta_time = match &mut self.input_source {
+            InputSource::Replay(player) => player.next_delta_time(),
+            InputSource::Live(_) => None
+        };
+
+        let mut timings = FrameTimings::default();
+        let mut context = EngineContext {
+            events: &self.publisher,
+            keymap: &self.keymap,
+            inputs: &self.frame_inputs,
+            seed: self.seed,
+            profiler: &self.profiler,
+            palette: &self.palette,
+            time: Time { tick: self.tick, ..Default::default() }
+        };
+        timings.dropped = match recorded_delta_time {
+            Some(delta_time) => self.game_handler.begin_recorded_tick(delta_time, &mut context),
+            None => self.game_handler.begin_tick(&mut context)
+        };
+
+        for plugin in self.plugins.iter_mut() {
+            for input in context.inputs.iter() {
+                plugin.on_input(&context, input);
+            }
+            plugin.pre_update(&context);
+        }
+
+        self.game_handler.update(&context, &mut timings);
+
+        for plugin in self.plugins.iter_mut() {
+            plugin.post_update(&context);
+        }
+
+        // Render with the same context so states see the timings they were updated with
+        if let Some(render_engine) = &mut self.render_engine {
+            let render_start = Instant::now();
+            let alpha = self.game_handler.alpha();
+            for commands in self.game_handler.state_machine.render(&context, alpha) {
+                render_engine.submit(&commands);
+            }
+            for plugin in self.plugins.iter_mut() {
+                let mut commands = CommandBuffer::new();
+                plugin.render(&context, &mut commands);
+                render_engine.submit(&commands);
+            }
+            render_engine.present();
+            timings.render = render_start.elapsed();
+        }
+
+        self.profiler.record(timings);
+
+        // Stop once the last recorded tick has ran, so the replay runs for exactly as long
+        // as the recording
+        if let InputSource::Replay(player) = &self.input_source {
+            if self.stop_after_replay && player.is_finished() {
+                self.publisher.send(EngineEvent::Stop);
+            }
+        }
+    }
+
+    /// The timings of the last tick
+    fn current_time(&self) -> Time {
+        Time {
+            runtime: self.game_handler.runtime,
+            simulation_rate: self.game_handler.simulation_rate(),
+            time_scale: self.game_handler.time_scale(),
+            paused: self.game_handler.is_paused(),
+            tick: self.tick,
+            ..Default::default()
+        }
+    }
+
+    /// Call a plugin hook that only needs the engine context outside of a tick
+    fn notify_plugins<F>(&mut self, hook: F) where
+        F: Fn(&mut dyn Plugin, &EngineContext) {
+        let context = EngineContext {
+            events: &self.publisher,
+            keymap: &self.keymap,
+            inputs: &[],
+            seed: self.seed,
+            profiler: &self.profiler,
+            palette: &self.palette,
+            time: self.current_time()
+        };
+        for plugin in self.plugins.iter_mut() {
+            hook(plugin.as_mut(), &context);
+        }
     }
 
     fn sync(&self) {
-        if let Some(render_fence) = &self.render_fence {
-            render_fence.0.close().pass();
+        if let Some(frame_pipeline) = &self.frame_pipeline {
+            frame_pipeline.wait_for_slot();
         }
     }
 
     /// Start and run the engine until program halts
     pub fn run(&mut self) {
+        self.notify_plugins(|plugin, context| plugin.on_startup(context));
         while self.running {
             self.handle_input();
             self.update_state();
             self.handle_engine_events();
             self.sync();
+            self.tick += 1;
+        }
+        self.shutdown();
+    }
+
+    /// Mark the engine as crashed after a panic was caught out of `run` and describe what it
+    /// was doing. States and plugins are still given their shutdown hooks afterwards
+    pub fn crashed(&mut self, panic: PanicDetails) -> CrashReport {
+        self.crashed = true;
+        let state_machine = &self.game_handler.state_machine;
+        CrashReport {
+            panic,
+            seed: self.seed,
+            tick: self.tick,
+            states: state_machine.state_names(),
+            recent_inputs: self.recent_inputs.iter().cloned().collect(),
+            state_data: state_machine.crash_data().into_iter().collect()
+        }
+    }
+
+    /// Pop every state, let plugins clean up and stop the renderer once it has displayed
+    /// the final frame. Only runs once; the engine can't be ran again afterwards
+    pub fn shutdown(&mut self) {
+        if self.shut_down {
+            return;
+        }
+        self.shut_down = true;
+        self.running = false;
+
+        // States and plugins still clean up after a panic or crash, but they may be in any
+        // condition so each hook is guarded and one failing doesn't stop the rest. A hook that
+        // panics while we are already unwinding aborts the process, which we can't prevent
+        let context = EngineContext {
+            events: &self.publisher,
+            keymap: &self.keymap,
+            inputs: &[],
+            seed: self.seed,
+            profiler: &self.profiler,
+            palette: &self.palette,
+            time: self.current_time()
+        };
+        self.game_handler.state_machine.clear(&context);
+        for plugin in self.plugins.iter_mut() {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| plugin.on_shutdown(&context)));
+            if result.is_err() {
+                log::error!("Plugin {} panicked while shutting down", plugin.name());
+            }
+        }
+
+        if let Some(render_engine) = &mut self.render_engine {
+            render_engine.shutdown();
+        }
+        log::info!("Engine shut down after {} ticks", self.tick);
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine_temp::game::state::State;
+    use crate::engine_temp::game::context::Context;
+    use crate::engine_temp::game::state::StateData;
+    use std::rc::Rc;
+    use std::cell::{ Cell, RefCell };
+    use std::sync::Arc;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    struct FixedCounter(Rc<Cell<usize>>);
+    impl State for FixedCounter {
+        fn update_fixed(&mut self, _ctx: &mut Context, _delta_time: f64) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    fn advance(handler: &mut GameHandler, delta_time: Duration) {
+        let events = EngineEventHandler::new();
+        let keymap = Keymap::new();
+        let mut context = EngineContext {
+            events: &events.get_publisher(),
+            keymap: &keymap,
+            inputs: &[],
+            seed: 0,
+            profiler: &Profiler::new(1),
+            palette: &Palette::standard(),
+            time: Time::default()
+        };
+        handler.advance_time(delta_time, &mut context);
+        handler.update(&context, &mut FrameTimings::default());
+    }
+
+    fn handler_with_counter() -> (GameHandler, Rc<Cell<usize>>) {
+        let counter = Rc::new(Cell::new(0));
+        let mut handler = GameHandler::new();
+        handler.set_simulation_rate(Duration::from_millis(10));
+        handler.state_machine.queue_push(Box::new(FixedCounter(counter.clone())));
+        (handler, counter)
+    }
+
+    #[test]
+    fn test_fixed_rate() {
+        let (mut handler, counter) = handler_with_counter();
+        advance(&mut handler, Duration::from_millis(35));
+        assert_eq!(counter.get(), 3);
+        advance(&mut handler, Duration::from_millis(5));
+        assert_eq!(counter.get(), 4);
+    }
+
+    #[test]
+    fn test_accumulator_clamp() {
+        let (mut handler, counter) = handler_with_counter();
+        handler.set_max_fixed_steps(4);
+        advance(&mut handler, Duration::from_secs(10));
+        assert_eq!(counter.get(), 4);
+        advance(&mut handler, Duration::from_millis(0));
+        assert_eq!(counter.get(), 4);
+    }
+
+    #[test]
+    fn test_extreme_timings() {
+        let (mut handler, counter) = handler_with_counter();
+        handler.set_time_scale(1e30);
+        assert_eq!(handler.time_scale(), MAX_TIME_SCALE);
+        handler.set_simulation_rate(Duration::MAX);
+        assert_eq!(handler.simulation_rate(), MAX_SIMULATION_RATE);
+        handler.set_simulation_rate(Duration::from_millis(10));
+
+        // a huge tick doesn't overflow, the time past the most fixed steps is just dropped
+        handler.set_max_fixed_steps(4);
+        advance(&mut handler, Duration::MAX);
+        advance(&mut handler, Duration::MAX);
+        assert_eq!(counter.get(), 8);
+    }
+
+    #[test]
+    fn test_pause_and_step() {
+        let (mut handler, counter) = handler_with_counter();
+        handler.pause();
+        advance(&mut handler, Duration::from_millis(50));
+        assert_eq!(counter.get(), 0);
+
+        handler.step();
+        handler.step();
+        advance(&mut handler, Duration::from_millis(50));
+        assert_eq!(counter.get(), 2);
+
+        handler.resume();
+        advance(&mut handler, Duration::from_millis(10));
+        assert_eq!(counter.get(), 3);
+
+        // stepping does nothing while running
+        handler.step();
+        advance(&mut handler, Duration::from_millis(0));
+        assert_eq!(counter.get(), 3);
+    }
+
+    #[test]
+    fn test_time_scale() {
+        let (mut handler, counter) = handler_with_counter();
+        handler.set_time_scale(0.5);
+        advance(&mut handler, Duration::from_millis(40));
+        assert_eq!(counter.get(), 2);
+
+        handler.set_time_scale(2.0);
+        advance(&mut handler, Duration::from_millis(20));
+        assert_eq!(counter.get(), 6);
+
+        handler.set_time_scale(-1.0);
+        assert_eq!(handler.time_scale(), 2.0);
+    }
+
+    struct CountingPlugin {
+        hooks: Rc<RefCell<Vec<&'static str>>>,
+        ticks: usize
+    }
+
+    impl Plugin for CountingPlugin {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn on_startup(&mut self, _ctx: &EngineContext) {
+            self.hooks.borrow_mut().push("startup");
         }
+
+        fn on_input(&mut self, _ctx: &EngineContext, _input: &Input) {
+            self.hooks.borrow_mut().push("input");
+        }
+
+        fn pre_update(&mut self, _ctx: &EngineContext) {
+            self.hooks.borrow_mut().push("pre_update");
+        }
+
+        fn post_update(&mut self, ctx: &EngineContext) {
+            self.hooks.borrow_mut().push("post_update");
+            self.ticks += 1;
+            if self.ticks == 2 {
+                ctx.events.send(EngineEvent::Stop);
+            }
+        }
+
+        fn on_shutdown(&mut self, _ctx: &EngineContext) {
+            self.hooks.borrow_mut().push("shutdown");
+        }
+    }
+
+    #[test]
+    fn test_plugins() {
+        let hooks = Rc::new(RefCell::new(Vec::new()));
+        let (send, recv) = mpsc::channel();
+        send.send(Input::Resize(10, 10)).unwrap();
+
+        let mut engine = Engine::new(recv, None)
+            .with_plugin(Box::new(CountingPlugin { hooks: hooks.clone(), ticks: 0 }));
+        engine.run();
+
+        assert_eq!(*hooks.borrow(), vec![
+            "startup",
+            "input",
+            "pre_update",
+            "post_update",
+            "pre_update",
+            "post_update",
+            "shutdown"
+        ]);
+        assert_eq!(engine.profiler.stats().frame_count, 2);
+    }
+
+    struct StopOnPush(Arc<AtomicUsize>);
+    impl State for StopOnPush {
+        fn on_push(&mut self, ctx: &mut Context, _data: Option<StateData>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            ctx.engine.events.send(EngineEvent::Stop);
+        }
+
+        fn on_pop(&mut self, _ctx: &mut Context) {
+            self.0.fetch_add(10, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_events_from_other_threads() {
+        let (_send, recv) = mpsc::channel();
+        let mut engine = Engine::new(recv, None);
+
+        let hooks = Arc::new(AtomicUsize::new(0));
+        let publisher = engine.publisher.clone();
+        let state_hooks = hooks.clone();
+        std::thread::spawn(move || {
+            publisher.send(EngineEvent::SetTimeScale(2.0));
+            publisher.send(EngineEvent::PushState(Box::new(|| Box::new(StopOnPush(state_hooks)))));
+        }).join().unwrap();
+
+        // the engine only stops once the state has been pushed, and pops it on shutdown
+        engine.run();
+        assert_eq!(engine.game_handler.time_scale(), 2.0);
+        assert_eq!(hooks.load(Ordering::SeqCst), 11);
+        assert!(engine.game_handler.state_machine.current_state().is_none());
+    }
+
+    struct PanicOnUpdate;
+    impl State for PanicOnUpdate {
+        fn update(&mut self, _ctx: &mut Context) {
+            panic!("update failed");
+        }
+
+        fn crash_data(&self) -> Option<serde_json::Value> {
+            Some(serde_json::json!({ "hp": 3 }))
+        }
+
+        fn on_pop(&mut self, _ctx: &mut Context) {
+            panic!("pop failed");
+        }
+
+        fn name(&self) -> &str {
+            "panic_on_update"
+        }
+    }
+
+    struct CountPops(Rc<Cell<usize>>);
+    impl State for CountPops {
+        fn on_pop(&mut self, _ctx: &mut Context) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_crash_report() {
+        let hooks = Rc::new(RefCell::new(Vec::new()));
+        let (send, recv) = mpsc::channel();
+        send.send(Input::Resize(20, 10)).unwrap();
+
+        let mut engine = Engine::new(recv, None)
+            .with_plugin(Box::new(CountingPlugin { hooks: hooks.clone(), ticks: 0 }));
+        engine.set_seed(99);
+        let pops = Rc::new(Cell::new(0));
+        engine.game_handler.state_machine.queue_push(Box::new(CountPops(pops.clone())));
+        engine.game_handler.state_machine.queue_push(Box::new(PanicOnUpdate));
+
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| engine.run())).unwrap_err();
+        let report = engine.crashed(PanicDetails::take(payload.as_ref()));
+        assert_eq!(report.panic.message, "update failed");
+        assert_eq!(report.seed, 99);
+        assert_eq!(report.tick, 0);
+        assert_eq!(report.states.len(), 2);
+        assert!(report.states[0].ends_with("CountPops"));
+        assert_eq!(report.states[1], "panic_on_update");
+        assert_eq!(report.recent_inputs, vec![RecordedInput { tick: 0, input: Input::Resize(20, 10) }]);
+        assert_eq!(report.state_data["panic_on_update"]["hp"], 3);
+
+        // states and plugins still clean up after a crash, even when a state panics again
+        // while being popped
+        drop(engine);
+        assert_eq!(pops.get(), 1);
+        assert_eq!(*hooks.borrow(), vec!["startup", "input", "pre_update", "shutdown"]);
+    }
+
+    #[test]
+    fn test_replay_tick_times() {
+        // playing back recorded tick times runs the same fixed updates regardless of how
+        // long the ticks take now
+        let mut replay = Replay::new(0);
+        replay.delta_times = vec![35_000_000, 0, 5_000_000, 12_000_000];
+
+        let counter = Rc::new(Cell::new(0));
+        let mut engine = Engine::from_replay(replay, None);
+        engine.set_stop_after_replay(true);
+        engine.game_handler.set_simulation_rate(Duration::from_millis(10));
+        engine.game_handler.state_machine.queue_push(Box::new(FixedCounter(counter.clone())));
+        engine.run();
+
+        assert_eq!(counter.get(), 5);
+    }
+
+    #[test]
+    fn test_shutdown_once() {
+        let hooks = Rc::new(RefCell::new(Vec::new()));
+        let (_send, recv) = mpsc::channel();
+        let mut engine = Engine::new(recv, None)
+            .with_plugin(Box::new(CountingPlugin { hooks: hooks.clone(), ticks: 0 }));
+
+        engine.shutdown();
+        engine.shutdown();
+        drop(engine);
+        assert_eq!(*hooks.borrow(), vec!["shutdown"]);
     }
 }
@@ -0,0 +1,283 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::sync::{ Arc, Mutex, MutexGuard, Condvar };
+
+struct PipelineState {
+    /// Frames handed to the renderer by the game
+    submitted: u64,
+    /// Frames the renderer has started working on
+    acquired: u64,
+    /// Frames the renderer has finished with
+    completed: u64,
+    closed: bool
+}
+
+struct Shared {
+    state: Mutex<PipelineState>,
+    changed: Condvar,
+    max_frames_in_flight: u64
+}
+
+/// Hands frames from the game thread to the render thread.
+///
+/// The game submits a frame once it is done writing to it and then waits for a slot
+/// before it starts on the next one, so it can only ever get `max_frames_in_flight`
+/// frames ahead of the renderer. The renderer acquires frames in the order they were
+/// submitted and releases them once they are displayed. Both sides block on a condvar
+/// rather than spinning, and since every change goes through the mutex anything written
+/// to a frame before it is submitted is visible to the renderer once it is acquired.
+///
+/// Closing the pipeline wakes up both sides. The renderer still acquires every frame
+/// that was submitted before the pipeline closed so nothing is lost on shutdown
+#[derive(Clone)]
+pub struct FramePipeline(Arc<Shared>);
+
+impl FramePipeline {
+    pub fn new(max_frames_in_flight: usize) -> FramePipeline {
+        FramePipeline(Arc::new(Shared {
+            state: Mutex::new(PipelineState {
+                submitted: 0,
+                acquired: 0,
+                completed: 0,
+                closed: false
+            }),
+            changed: Condvar::new(),
+            max_frames_in_flight: max_frames_in_flight.max(1) as u64
+        }))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PipelineState> {
+        self.0.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, PipelineState>) -> MutexGuard<'a, PipelineState> {
+        self.0.changed.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Hand the current frame to the renderer. Returns the number of the frame
+    pub fn submit(&self) -> u64 {
+        let mut state = self.lock();
+        let frame = state.submitted;
+        state.submitted += 1;
+        self.0.changed.notify_all();
+        frame
+    }
+
+    /// Block until there are fewer than the max frames in flight so the next frame can
+    /// be written. Returns false if the pipeline was closed
+    pub fn wait_for_slot(&self) -> bool {
+        let mut state = self.lock();
+        while !state.closed && state.submitted - state.completed >= self.0.max_frames_in_flight {
+            state = self.wait(state);
+        }
+        !state.closed
+    }
+
+    /// Block until a submitted frame is ready to be displayed and return its number.
+    /// Returns None once the pipeline is closed and every submitted frame was acquired
+    pub fn acquire(&self) -> Option<u64> {
+        let mut state = self.lock();
+        while !state.closed && state.acquired == state.submitted {
+            state = self.wait(state);
+        }
+
+        if state.acquired == state.submitted {
+            return None;
+        }
+        let frame = state.acquired;
+        state.acquired += 1;
+        Some(frame)
+    }
+
+    /// Mark the oldest acquired frame as displayed, freeing its slot for the game
+    pub fn release(&self) {
+        let mut state = self.lock();
+        if state.completed < state.acquired {
+            state.completed += 1;
+            self.0.changed.notify_all();
+        } else {
+            log::warn!("Released a frame that was never acquired");
+        }
+    }
+
+    /// Stop the pipeline and wake anything waiting on it
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.0.changed.notify_all();
+    }
+
+    /// How many frames have been submitted that the renderer has not finished with
+    #[cfg(test)]
+    pub fn frames_in_flight(&self) -> u64 {
+        let state = self.lock();
+        state.submitted - state.completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{ Duration, Instant };
+
+    macro_rules! assert_timeout{
+        ($left:expr, $timeout:expr, $delay:expr) => {
+            assert_eq!(thread::spawn(move || {
+                let start_time = Instant::now();
+                while !$left.is_finished() {
+                    if Instant::now() - start_time >= Duration::from_millis($timeout) {
+                        panic!("Did not terminate in time");
+                    }
+                    thread::sleep(Duration::from_millis($delay));
+                }
+                $left.join()
+            }).join().unwrap().is_ok(), true);
+        }
+    }
+
+    #[test]
+    fn test_bounded_frames() {
+        let pipeline = FramePipeline::new(2);
+        assert!(pipeline.wait_for_slot());
+        assert_eq!(pipeline.submit(), 0);
+        assert!(pipeline.wait_for_slot());
+        assert_eq!(pipeline.submit(), 1);
+        assert_eq!(pipeline.frames_in_flight(), 2);
+
+        // the game is now two frames ahead and has to wait for the renderer
+        let game = pipeline.clone();
+        let t0 = thread::spawn(move || {
+            assert!(game.wait_for_slot());
+            game.frames_in_flight()
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert!(!t0.is_finished());
+
+        assert_eq!(pipeline.acquire(), Some(0));
+        pipeline.release();
+        assert_eq!(t0.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_close_wakes_waiters() {
+        let pipeline = FramePipeline::new(1);
+        pipeline.submit();
+
+        let game = pipeline.clone();
+        let t0 = thread::spawn(move || game.wait_for_slot());
+
+        let renderer = FramePipeline::new(1);
+        let r0 = renderer.clone();
+        let t1 = thread::spawn(move || r0.acquire());
+
+        thread::sleep(Duration::from_millis(50));
+        pipeline.close();
+        renderer.close();
+        assert!(!t0.join().unwrap());
+        assert_eq!(t1.join().unwrap(), None);
+    }
+
+    #[test]
+    fn test_drain_after_close() {
+        let pipeline = FramePipeline::new(3);
+        pipeline.submit();
+        pipeline.submit();
+        pipeline.close();
+
+        assert_eq!(pipeline.acquire(), Some(0));
+        pipeline.release();
+        assert_eq!(pipeline.acquire(), Some(1));
+        pipeline.release();
+        assert_eq!(pipeline.acquire(), None);
+        assert_eq!(pipeline.frames_in_flight(), 0);
+    }
+
+    #[test]
+    fn test_no_deadlock() {
+        const FRAMES: u64 = 10_000;
+        let pipeline = FramePipeline::new(2);
+
+        let renderer = pipeline.clone();
+        let render_thread = thread::spawn(move || {
+            let mut expected = 0;
+            while let Some(frame) = renderer.acquire() {
+                assert_eq!(frame, expected);
+                expected += 1;
+                renderer.release();
+            }
+            expected
+        });
+
+        let game = pipeline.clone();
+        let game_thread = thread::spawn(move || {
+            for _ in 0..FRAMES {
+                assert!(game.wait_for_slot());
+                assert!(game.frames_in_flight() < 2);
+                game.submit();
+            }
+            game.close();
+        });
+
+        assert_timeout!(game_thread, 5000, 10);
+        assert_eq!(render_thread.join().unwrap(), FRAMES);
+    }
+
+    #[test]
+    fn test_throughput() {
+        // with a slow renderer the game should be kept at the renderer's pace, never
+        // submitting a frame until the one two behind it has been drawn
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        enum Event {
+            Submit(u64),
+            Render(u64)
+        }
+
+        const FRAMES: u64 = 100;
+        let pipeline = FramePipeline::new(2);
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let renderer = pipeline.clone();
+        let render_events = events.clone();
+        let render_thread = thread::spawn(move || {
+            while let Some(frame) = renderer.acquire() {
+                thread::sleep(Duration::from_millis(1));
+                render_events.lock().unwrap().push(Event::Render(frame));
+                renderer.release();
+            }
+        });
+
+        for frame in 0..FRAMES {
+            assert!(pipeline.wait_for_slot());
+            events.lock().unwrap().push(Event::Submit(frame));
+            pipeline.submit();
+        }
+        pipeline.close();
+        render_thread.join().unwrap();
+
+        let events = events.lock().unwrap();
+        let position = |event| events.iter().position(|e| *e == event).unwrap();
+        assert_eq!(events.len() as u64, FRAMES * 2);
+        for frame in 0..FRAMES {
+            assert!(position(Event::Submit(frame)) < position(Event::Render(frame)));
+            if frame >= 2 {
+                assert!(position(Event::Render(frame - 2)) < position(Event::Submit(frame)));
+            }
+        }
+    }
+}
@@ -18,4 +18,5 @@
 
 pub mod state_machine;
 pub mod state;
+pub mod context;
 
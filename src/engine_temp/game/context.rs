@@ -0,0 +1,62 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::engine_temp::engine::EngineEventPublisher;
+use crate::engine_temp::game::state_machine::StateTransitions;
+use crate::engine_temp::input::Input;
+use crate::engine_temp::input::keymap::Keymap;
+use crate::engine_temp::profiler::Profiler;
+use crate::renderer::palette::Palette;
+use std::time::Duration;
+
+/// Timing information for the current tick
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Time {
+    /// Real time since the engine started
+    pub runtime: Duration,
+    /// Real time since the last tick
+    pub delta_time: Duration,
+    /// How often the fixed update is ran
+    pub simulation_rate: Duration,
+    pub time_scale: f64,
+    pub paused: bool,
+    /// How many ticks the engine has ran
+    pub tick: u64
+}
+
+/// Everything the engine exposes to states during a tick
+pub struct EngineContext<'a> {
+    pub events: &'a EngineEventPublisher,
+    pub keymap: &'a Keymap,
+    /// Every input the engine received this tick
+    pub inputs: &'a [Input],
+    /// The seed this run was started with
+    pub seed: u64,
+    /// Timings of the previous frames
+    pub profiler: &'a Profiler,
+    /// Named colours states should draw with, so the look can be changed from a file
+    pub palette: &'a Palette,
+    pub time: Time
+}
+
+/// Passed to every state callback so a state can change the state stack and talk to
+/// the engine
+pub struct Context<'a, 'b> {
+    pub states: &'a mut StateTransitions,
+    pub engine: &'a EngineContext<'b>
+}
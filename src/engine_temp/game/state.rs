@@ -15,18 +15,60 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
-use crate::engine_temp::game::state_machine::StateMachine;
+use crate::engine_temp::game::context::Context;
+use crate::engine_temp::input::Input;
+use crate::engine_temp::input::action::{ Action, InputContext };
+use crate::renderer::crossterm::command_buffer::CommandBuffer;
+use std::any::Any;
+
+/// Data passed into a state when it is pushed, or returned from a state when it is popped
+pub type StateData = Box<dyn Any>;
+
+/// Whether a state handled an input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputResult {
+    /// The input was handled and should not be seen by any other state
+    Consumed,
+    /// The input was not handled
+    Ignored
+}
 
 pub trait State {
-    fn on_push(&mut self) {}
-    fn on_pop(&mut self) {}
+    /// Called when the state is pushed onto the stack with the data it was pushed with
+    fn on_push(&mut self, _ctx: &mut Context, _data: Option<StateData>) {}
+    fn on_pop(&mut self, _ctx: &mut Context) {}
+    /// Called when the state above this one is popped with the result it was popped with
+    fn on_resume(&mut self, _ctx: &mut Context, _result: Option<StateData>) {}
 
-    fn init(&mut self) {}
-    fn deinit(&mut self) {}
+    fn init(&mut self, _ctx: &mut Context) {}
+    fn deinit(&mut self, _ctx: &mut Context) {}
 
-    fn update(&mut self) {}
-    fn update_fixed(&mut self, delta_time: f64) {}
-    fn pre_update(&mut self) {}
-    fn post_update(&mut self) {}
-}
+    fn update(&mut self, _ctx: &mut Context) {}
+    fn update_fixed(&mut self, _ctx: &mut Context, _delta_time: f64) {}
+    fn pre_update(&mut self, _ctx: &mut Context) {}
+    fn post_update(&mut self, _ctx: &mut Context) {}
+
+    /// Record the draw commands for this state. Alpha is how far between the last fixed
+    /// update and the next one we are, to interpolate with
+    fn render(&mut self, _ctx: &mut Context, _commands: &mut CommandBuffer, _alpha: f64) {}
 
+    /// Called when a save is requested. The state should persist anything it needs to
+    fn on_save(&mut self, _ctx: &mut Context) {}
+    /// Anything about this state that would help reproduce a crash. Added to crash reports
+    fn crash_data(&self) -> Option<serde_json::Value> { None }
+
+    /// The name of this state in logs and crash reports
+    fn name(&self) -> &str { std::any::type_name::<Self>() }
+
+    /// If true, the state beneath this one keeps updating while this state is on top of it
+    fn updates_below(&self) -> bool { false }
+    /// If true, the state beneath this one is rendered before this state
+    fn renders_below(&self) -> bool { false }
+
+    /// The context key presses are translated to actions in for this state
+    fn input_context(&self) -> InputContext { InputContext::Gameplay }
+    /// Handle an input. The action is what the input maps to in this state's input context
+    fn on_input(&mut self, _ctx: &mut Context, _input: &Input, _action: Option<Action>) -> InputResult { InputResult::Ignored }
+    /// If true, inputs this state ignores are passed to the state beneath it
+    fn input_falls_through(&self) -> bool { false }
+}
@@ -15,85 +15,322 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
-use crate::engine_temp::game::state::State;
+use crate::engine_temp::game::state::{ State, StateData, InputResult };
+use crate::engine_temp::game::context::{ Context, EngineContext };
+use crate::engine_temp::input::Input;
+use crate::renderer::crossterm::command_buffer::CommandBuffer;
 use std::collections::VecDeque;
+use std::panic::{ self, AssertUnwindSafe };
+
+enum Transition {
+    Push(Box<dyn State>, Option<StateData>),
+    Pop(Option<StateData>),
+    Replace(Box<dyn State>, Option<StateData>),
+    ClearAndPush(Box<dyn State>, Option<StateData>)
+}
+
+/// Changes to the state stack that will be applied in order at the start of the next tick
+pub struct StateTransitions {
+    queued_transitions: VecDeque<Transition>,
+}
+
+impl StateTransitions {
+    fn new() -> StateTransitions {
+        StateTransitions {
+            queued_transitions: VecDeque::new(),
+        }
+    }
+
+    pub fn queue_push(&mut self, state: Box<dyn State>) {
+        self.queued_transitions.push_back(Transition::Push(state, None));
+    }
+
+    /// Push a state and give it data when it is pushed
+    pub fn queue_push_with(&mut self, state: Box<dyn State>, data: StateData) {
+        self.queued_transitions.push_back(Transition::Push(state, Some(data)));
+    }
+
+    pub fn queue_pop(&mut self) {
+        self.queued_transitions.push_back(Transition::Pop(None));
+    }
+
+    /// Pop the top state and give a result to the state beneath it
+    pub fn queue_pop_with(&mut self, result: StateData) {
+        self.queued_transitions.push_back(Transition::Pop(Some(result)));
+    }
+
+    /// Pop the top state and push a new one in its place. The state beneath is not resumed
+    pub fn queue_replace(&mut self, state: Box<dyn State>) {
+        self.queued_transitions.push_back(Transition::Replace(state, None));
+    }
+
+    pub fn queue_replace_with(&mut self, state: Box<dyn State>, data: StateData) {
+        self.queued_transitions.push_back(Transition::Replace(state, Some(data)));
+    }
+
+    /// Pop every state and push a new one onto the empty stack
+    pub fn queue_clear_and_push(&mut self, state: Box<dyn State>) {
+        self.queued_transitions.push_back(Transition::ClearAndPush(state, None));
+    }
+
+    pub fn queue_clear_and_push_with(&mut self, state: Box<dyn State>, data: StateData) {
+        self.queued_transitions.push_back(Transition::ClearAndPush(state, Some(data)));
+    }
+}
 
 pub struct StateMachine {
-    queued_states: VecDeque<Box<dyn State>>,
+    transitions: StateTransitions,
     state_stack: Vec<Box<dyn State>>,
-    pop_count: usize,
 }
 
 impl StateMachine {
     pub fn new() -> StateMachine {
         StateMachine {
-            queued_states: VecDeque::new(),
+            transitions: StateTransitions::new(),
             state_stack: Vec::new(),
-            pop_count: 0
         }
     }
 
     pub fn queue_push(&mut self, state: Box<dyn State>) {
-        self.queued_states.push_back(state);
+        self.transitions.queue_push(state);
     }
 
     pub fn queue_pop(&mut self) {
-        self.pop_count += 1
+        self.transitions.queue_pop();
+    }
+
+    pub fn queue_replace(&mut self, state: Box<dyn State>) {
+        self.transitions.queue_replace(state);
     }
 
-    pub fn pre_update(&mut self) {
-        while self.pop_count > 0 {
-            self.state_stack.last_mut().unwrap().deinit();
-            let mut state = self.state_stack.pop().unwrap();
-            state.on_pop();
-            if let Some(stack_state) = self.state_stack.last_mut() {
-                stack_state.init();
+    /// Remove the top state. Returns false if there was nothing to remove
+    fn pop_state(state_stack: &mut Vec<Box<dyn State>>, ctx: &mut Context) -> bool {
+        match state_stack.pop() {
+            Some(mut state) => {
+                state.deinit(ctx);
+                state.on_pop(ctx);
+                true
+            },
+            None => {
+                log::warn!("Attempted to pop a state from an empty state stack");
+                false
             }
-            self.pop_count -= 1;
         }
+    }
+
+    fn push_state(state_stack: &mut Vec<Box<dyn State>>, ctx: &mut Context, mut state: Box<dyn State>, data: Option<StateData>) {
+        if let Some(stack_state) = state_stack.last_mut() {
+            stack_state.deinit(ctx);
+        }
+        state.on_push(ctx, data);
+        state.init(ctx);
+        state_stack.push(state);
+    }
+
+    pub fn pre_update(&mut self, engine: &EngineContext) {
+        let ctx = &mut Context { states: &mut self.transitions, engine };
+
+        while let Some(transition) = ctx.states.queued_transitions.pop_front() {
+            match transition {
+                Transition::Push(state, data) => {
+                    StateMachine::push_state(&mut self.state_stack, ctx, state, data);
+                },
+                Transition::Pop(result) => {
+                    if StateMachine::pop_state(&mut self.state_stack, ctx) {
+                        if let Some(stack_state) = self.state_stack.last_mut() {
+                            stack_state.on_resume(ctx, result);
+                            stack_state.init(ctx);
+                        }
+                    }
+                },
+                Transition::Replace(state, data) => {
+                    StateMachine::pop_state(&mut self.state_stack, ctx);
+                    self.state_stack.push(state);
+                    let state = self.state_stack.last_mut().unwrap();
+                    state.on_push(ctx, data);
+                    state.init(ctx);
+                },
+                Transition::ClearAndPush(state, data) => {
+                    // Only the top state is initialised, everything beneath it just
+                    // needs to be popped
+                    if let Some(stack_state) = self.state_stack.last_mut() {
+                        stack_state.deinit(ctx);
+                    }
+                    while let Some(mut stack_state) = self.state_stack.pop() {
+                        stack_state.on_pop(ctx);
+                    }
+                    StateMachine::push_state(&mut self.state_stack, ctx, state, data);
+                }
+            }
+        }
+
+        let first_updated = StateMachine::first_updated(&self.state_stack);
+        for state in self.state_stack[first_updated..].iter_mut() {
+            state.pre_update(ctx);
+        }
+    }
+
+    /// The index of the lowest state on the stack that is updated this tick
+    fn first_updated(state_stack: &[Box<dyn State>]) -> usize {
+        StateMachine::first_below(state_stack, |state| state.updates_below())
+    }
+
+    /// The index of the lowest state on the stack that is rendered this tick
+    fn first_rendered(state_stack: &[Box<dyn State>]) -> usize {
+        StateMachine::first_below(state_stack, |state| state.renders_below())
+    }
+
+    /// Walk down from the top of the stack while states let us see beneath them
+    fn first_below<F>(state_stack: &[Box<dyn State>], sees_below: F) -> usize where
+        F: Fn(&dyn State) -> bool {
+        let mut index = state_stack.len().saturating_sub(1);
+        while index > 0 && sees_below(state_stack[index].as_ref()) {
+            index -= 1;
+        }
+        index
+    }
+
+    /// Give an input to the top state. If it isn't consumed and the state lets input fall
+    /// through, it is given to the state beneath it, and so on
+    pub fn handle_input(&mut self, engine: &EngineContext, input: &Input) -> InputResult {
+        let ctx = &mut Context { states: &mut self.transitions, engine };
+        for state in self.state_stack.iter_mut().rev() {
+            let action = engine.keymap.translate(state.input_context(), input);
+            if state.on_input(ctx, input, action) == InputResult::Consumed {
+                return InputResult::Consumed
+            }
 
-        while let Some(state) = self.queued_states.pop_front() {
-            if let Some(stack_state) = self.state_stack.last_mut() {
-                stack_state.deinit();
+            if !state.input_falls_through() {
+                break;
             }
-            self.state_stack.push(state);
-            self.state_stack.last_mut().unwrap().on_push();
-            self.state_stack.last_mut().unwrap().init();
         }
+        InputResult::Ignored
+    }
 
+    pub fn update(&mut self, engine: &EngineContext) {
+        let first_updated = StateMachine::first_updated(&self.state_stack);
+        let ctx = &mut Context { states: &mut self.transitions, engine };
+        for state in self.state_stack[first_updated..].iter_mut() {
+            state.update(ctx);
+        }
+    }
 
-        if let Some(state) = self.state_stack.last_mut() {
-            state.pre_update();
+    pub fn update_fixed(&mut self, engine: &EngineContext, delta_time: f64) {
+        let first_updated = StateMachine::first_updated(&self.state_stack);
+        let ctx = &mut Context { states: &mut self.transitions, engine };
+        for state in self.state_stack[first_updated..].iter_mut() {
+            state.update_fixed(ctx, delta_time);
         }
     }
 
-    pub fn update(&mut self) {
-        if let Some(state) = self.state_stack.last_mut() {
-            state.update();
+    pub fn post_update(&mut self, engine: &EngineContext) {
+        let first_updated = StateMachine::first_updated(&self.state_stack);
+        let ctx = &mut Context { states: &mut self.transitions, engine };
+        for state in self.state_stack[first_updated..].iter_mut() {
+            state.post_update(ctx);
         }
     }
 
-    pub fn update_fixed(&mut self, delta_time: f64) {
-        if let Some(state) = self.state_stack.last_mut() {
-            state.update_fixed(delta_time);
+    /// Record draw commands for every visible state, from the bottom of the stack up
+    pub fn render(&mut self, engine: &EngineContext, alpha: f64) -> Vec<CommandBuffer> {
+        let first_rendered = StateMachine::first_rendered(&self.state_stack);
+        let ctx = &mut Context { states: &mut self.transitions, engine };
+        self.state_stack[first_rendered..].iter_mut().map(|state| {
+            let mut commands = CommandBuffer::new();
+            state.render(ctx, &mut commands, alpha);
+            commands
+        }).collect()
+    }
+
+    /// Ask every state on the stack to save, from the bottom up
+    pub fn save(&mut self, engine: &EngineContext) {
+        let ctx = &mut Context { states: &mut self.transitions, engine };
+        for state in self.state_stack.iter_mut() {
+            state.on_save(ctx);
         }
- 
     }
 
-    pub fn post_update(&mut self) {
-        if let Some(state) = self.state_stack.last_mut() {
-            state.post_update();
+    /// Pop every state on the stack, from the top down, and drop any queued transitions.
+    /// Used when the engine shuts down so states can clean up after themselves. A state
+    /// whose hook panics is logged and dropped so the states under it are still popped
+    pub fn clear(&mut self, engine: &EngineContext) {
+        let ctx = &mut Context { states: &mut self.transitions, engine };
+        if let Some(stack_state) = self.state_stack.last_mut() {
+            let name = stack_state.name().to_string();
+            if panic::catch_unwind(AssertUnwindSafe(|| stack_state.deinit(ctx))).is_err() {
+                log::error!("State {} panicked while being deinitialised", name);
+            }
+        }
+        while let Some(mut stack_state) = self.state_stack.pop() {
+            let name = stack_state.name().to_string();
+            if panic::catch_unwind(AssertUnwindSafe(|| stack_state.on_pop(ctx))).is_err() {
+                log::error!("State {} panicked while being popped", name);
+            }
         }
+        self.transitions.queued_transitions.clear();
     }
 
-    pub fn current_state(&self) -> Option<&Box<dyn State>> {
-        self.state_stack.last()
+    /// The names of every state on the stack, from the bottom up
+    pub fn state_names(&self) -> Vec<String> {
+        self.state_stack.iter().map(|state| state.name().to_string()).collect()
+    }
+
+    /// The crash data of every state that has any, along with the state's name
+    pub fn crash_data(&self) -> Vec<(String, serde_json::Value)> {
+        self.state_stack.iter()
+            .filter_map(|state| state.crash_data().map(|data| (state.name().to_string(), data)))
+            .collect()
+    }
+
+    pub fn current_state(&self) -> Option<&dyn State> {
+        self.state_stack.last().map(|state| state.as_ref())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::engine_temp::input::action::{ Action, InputContext };
+    use crate::engine_temp::input::keymap::{ Keymap, KeymapPreset };
+    use crate::engine_temp::game::context::Time;
+    use crate::engine_temp::profiler::Profiler;
+    use crate::renderer::palette::Palette;
+    use crate::engine_temp::engine::{ EngineEventHandler, EngineEventPublisher };
+    use std::rc::Rc;
+    use std::cell::{ Cell, RefCell };
+
+    struct TestEngine {
+        _events: EngineEventHandler,
+        publisher: EngineEventPublisher,
+        profiler: Profiler,
+        palette: Palette,
+        keymap: Keymap
+    }
+
+    impl TestEngine {
+        fn new() -> TestEngine {
+            let events = EngineEventHandler::new();
+            TestEngine {
+                publisher: events.get_publisher(),
+                profiler: Profiler::new(1),
+                palette: Palette::standard(),
+                _events: events,
+                keymap: Keymap::preset(KeymapPreset::ViKeys)
+            }
+        }
+
+        fn context(&self) -> EngineContext<'_> {
+            EngineContext {
+                events: &self.publisher,
+                keymap: &self.keymap,
+                inputs: &[],
+                seed: 0,
+                profiler: &self.profiler,
+                palette: &self.palette,
+                time: Time::default()
+            }
+        }
+    }
     
     struct TestState{
         counter: usize,
@@ -129,42 +366,42 @@ mod tests {
     }
 
     impl State for TestState {
-        fn on_push(&mut self) {
+        fn on_push(&mut self, _ctx: &mut Context, _data: Option<StateData>) {
             let i = self.increment();
             assert_eq!(self.on_push_counter, i);
         }
 
-        fn on_pop(&mut self) {
+        fn on_pop(&mut self, _ctx: &mut Context) {
             let i = self.increment();
             assert_eq!(self.on_pop_counter, i);
         }
 
-        fn init(&mut self) {
+        fn init(&mut self, _ctx: &mut Context) {
             let i = self.increment();
             assert_eq!(self.init_counter, i);
         }
 
-        fn deinit(&mut self) {
+        fn deinit(&mut self, _ctx: &mut Context) {
             let i = self.increment();
             assert_eq!(self.deinit_counter, i);
         }
 
-        fn pre_update(&mut self) {
+        fn pre_update(&mut self, _ctx: &mut Context) {
             let i = self.increment();
             assert_eq!(self.pre_update_counter, i);
         }
 
-        fn update(&mut self) {
+        fn update(&mut self, _ctx: &mut Context) {
             let i = self.increment();
             assert_eq!(self.update_counter, i);
         }
 
-        fn update_fixed(&mut self, _delta_time: f64) {
+        fn update_fixed(&mut self, _ctx: &mut Context, _delta_time: f64) {
             let i = self.counter;
             assert_eq!(self.update_fixed_counter, i);
         }
 
-        fn post_update(&mut self) {
+        fn post_update(&mut self, _ctx: &mut Context) {
             let i = self.increment();
             assert_eq!(self.post_update_counter, i - 1);
         }
@@ -172,15 +409,17 @@ mod tests {
 
     #[test]
     fn test_empty() {
+        let engine = TestEngine::new();
         let mut sm = StateMachine::new();
-        sm.pre_update();
-        sm.update();
-        sm.update_fixed(0.0);
-        sm.post_update();
+        sm.pre_update(&engine.context());
+        sm.update(&engine.context());
+        sm.update_fixed(&engine.context(), 0.0);
+        sm.post_update(&engine.context());
     }
 
     #[test]
     fn test_single_push() {
+        let engine = TestEngine::new();
         let mut sm = StateMachine::new();
         let ts = TestState::new(
             1,
@@ -194,12 +433,13 @@ mod tests {
         );
         sm.queue_push(Box::new(ts));
         assert_eq!(sm.current_state().is_none(), true);
-        sm.pre_update();
+        sm.pre_update(&engine.context());
         assert_eq!(sm.current_state().is_none(), false);
     }
 
     #[test]
     fn test_pop() {
+        let engine = TestEngine::new();
         let mut sm = StateMachine::new();
         let ts = TestState::new(
             1,
@@ -213,13 +453,14 @@ mod tests {
         );
 
         sm.queue_push(Box::new(ts));
-        sm.pre_update();
+        sm.pre_update(&engine.context());
         sm.queue_pop();
-        sm.pre_update();
+        sm.pre_update(&engine.context());
     }
 
     #[test]
     fn test_full_tick() {
+        let engine = TestEngine::new();
         let mut sm = StateMachine::new();
         let ts = TestState::new(
             1,
@@ -233,15 +474,270 @@ mod tests {
         );
 
         sm.queue_push(Box::new(ts));
-        sm.pre_update();
-        sm.update();
-        sm.update_fixed(0.0);
-        sm.post_update();
+        sm.pre_update(&engine.context());
+        sm.update(&engine.context());
+        sm.update_fixed(&engine.context(), 0.0);
+        sm.post_update(&engine.context());
+    }
+
+    struct InputState {
+        context: InputContext,
+        falls_through: bool,
+        consumes: Option<Action>,
+        seen: Rc<RefCell<Vec<Option<Action>>>>
+    }
+
+    impl State for InputState {
+        fn input_context(&self) -> InputContext {
+            self.context
+        }
+
+        fn on_input(&mut self, _ctx: &mut Context, _input: &Input, action: Option<Action>) -> InputResult {
+            self.seen.borrow_mut().push(action);
+            if action.is_some() && action == self.consumes {
+                return InputResult::Consumed
+            }
+            InputResult::Ignored
+        }
+
+        fn input_falls_through(&self) -> bool {
+            self.falls_through
+        }
+    }
+
+    #[test]
+    fn test_input_routing() {
+        let escape = Input::Key("escape".parse().unwrap());
+        let north = Input::Key("k".parse().unwrap());
+
+        let below = Rc::new(RefCell::new(Vec::new()));
+        let above = Rc::new(RefCell::new(Vec::new()));
+
+        let engine = TestEngine::new();
+        let mut sm = StateMachine::new();
+        sm.queue_push(Box::new(InputState {
+            context: InputContext::Gameplay,
+            falls_through: false,
+            consumes: Some(Action::MoveNorth),
+            seen: below.clone()
+        }));
+        sm.queue_push(Box::new(InputState {
+            context: InputContext::Menu,
+            falls_through: true,
+            consumes: Some(Action::Cancel),
+            seen: above.clone()
+        }));
+        sm.pre_update(&engine.context());
+
+        // consumed by the top state, never reaches the bottom
+        assert_eq!(sm.handle_input(&engine.context(), &escape), InputResult::Consumed);
+        assert_eq!(*above.borrow(), vec![Some(Action::Cancel)]);
+        assert!(below.borrow().is_empty());
+
+        // ignored by the top state and falls through to the bottom
+        assert_eq!(sm.handle_input(&engine.context(), &north), InputResult::Consumed);
+        assert_eq!(*above.borrow(), vec![Some(Action::Cancel), None]);
+        assert_eq!(*below.borrow(), vec![Some(Action::MoveNorth)]);
+
+        // nobody wants this
+        assert_eq!(sm.handle_input(&engine.context(), &Input::CloseGame), InputResult::Ignored);
+        assert_eq!(below.borrow().len(), 2);
+    }
+
+    struct LoggingState {
+        name: &'static str,
+        log: Rc<RefCell<Vec<String>>>
+    }
+
+    impl LoggingState {
+        fn boxed(name: &'static str, log: &Rc<RefCell<Vec<String>>>) -> Box<LoggingState> {
+            Box::new(LoggingState { name, log: log.clone() })
+        }
+
+        fn write(&self, event: String) {
+            self.log.borrow_mut().push(format!("{} {}", self.name, event));
+        }
+    }
+
+    impl State for LoggingState {
+        fn on_push(&mut self, _ctx: &mut Context, data: Option<StateData>) {
+            let data = data.and_then(|d| d.downcast::<u32>().ok()).map(|d| *d);
+            self.write(format!("push {:?}", data));
+        }
+
+        fn on_pop(&mut self, _ctx: &mut Context) {
+            self.write("pop".to_string());
+        }
+
+        fn on_resume(&mut self, _ctx: &mut Context, result: Option<StateData>) {
+            let result = result.and_then(|r| r.downcast::<&str>().ok()).map(|r| *r);
+            self.write(format!("resume {:?}", result));
+        }
+    }
+
+    #[test]
+    fn test_data_and_results() {
+        let engine = TestEngine::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut sm = StateMachine::new();
+
+        sm.queue_push(LoggingState::boxed("game", &log));
+        sm.transitions.queue_push_with(LoggingState::boxed("inventory", &log), Box::new(7u32));
+        sm.pre_update(&engine.context());
+        sm.transitions.queue_pop_with(Box::new("sword"));
+        sm.pre_update(&engine.context());
+        sm.queue_pop();
+        sm.pre_update(&engine.context());
+        // popping an empty stack does nothing
+        sm.queue_pop();
+        sm.pre_update(&engine.context());
+
+        assert_eq!(*log.borrow(), vec![
+            "game push None",
+            "inventory push Some(7)",
+            "inventory pop",
+            "game resume Some(\"sword\")",
+            "game pop",
+        ]);
+        assert!(sm.current_state().is_none());
+    }
+
+    #[test]
+    fn test_replace_and_clear() {
+        let engine = TestEngine::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut sm = StateMachine::new();
+
+        sm.queue_push(LoggingState::boxed("a", &log));
+        sm.queue_push(LoggingState::boxed("b", &log));
+        sm.transitions.queue_replace_with(LoggingState::boxed("c", &log), Box::new(1u32));
+        sm.pre_update(&engine.context());
+        assert_eq!(sm.state_stack.len(), 2);
+
+        sm.transitions.queue_clear_and_push(LoggingState::boxed("d", &log));
+        sm.pre_update(&engine.context());
+        assert_eq!(sm.state_stack.len(), 1);
+
+        assert_eq!(*log.borrow(), vec![
+            "a push None",
+            "b push None",
+            "b pop",
+            "c push Some(1)",
+            "c pop",
+            "a pop",
+            "d push None",
+        ]);
+    }
+
+    #[test]
+    fn test_clear() {
+        let engine = TestEngine::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut sm = StateMachine::new();
+
+        sm.queue_push(LoggingState::boxed("a", &log));
+        sm.queue_push(LoggingState::boxed("b", &log));
+        sm.pre_update(&engine.context());
+
+        sm.queue_push(LoggingState::boxed("c", &log));
+        sm.clear(&engine.context());
+        assert!(sm.current_state().is_none());
+
+        // the queued push was dropped with the stack
+        sm.pre_update(&engine.context());
+        assert!(sm.current_state().is_none());
+
+        assert_eq!(*log.borrow(), vec![
+            "a push None",
+            "b push None",
+            "b pop",
+            "a pop",
+        ]);
+    }
+
+    struct OverlayState {
+        updates_below: bool,
+        renders_below: bool,
+        updates: Rc<Cell<usize>>
+    }
+
+    impl OverlayState {
+        fn boxed(updates_below: bool, renders_below: bool, updates: &Rc<Cell<usize>>) -> Box<OverlayState> {
+            Box::new(OverlayState { updates_below, renders_below, updates: updates.clone() })
+        }
+    }
+
+    impl State for OverlayState {
+        fn update(&mut self, _ctx: &mut Context) {
+            self.updates.set(self.updates.get() + 1);
+        }
+
+        fn render(&mut self, _ctx: &mut Context, _commands: &mut CommandBuffer, alpha: f64) {
+            assert_eq!(alpha, 0.5);
+            self.updates.set(self.updates.get() + 10);
+        }
+
+        fn updates_below(&self) -> bool {
+            self.updates_below
+        }
+
+        fn renders_below(&self) -> bool {
+            self.renders_below
+        }
+    }
+
+    #[test]
+    fn test_overlays() {
+        let engine = TestEngine::new();
+        let bottom = Rc::new(Cell::new(0));
+        let middle = Rc::new(Cell::new(0));
+        let top = Rc::new(Cell::new(0));
+
+        let mut sm = StateMachine::new();
+        sm.queue_push(OverlayState::boxed(true, true, &bottom));
+        sm.queue_push(OverlayState::boxed(false, true, &middle));
+        sm.queue_push(OverlayState::boxed(true, false, &top));
+        sm.pre_update(&engine.context());
+        sm.update(&engine.context());
+
+        // the top state lets the middle update, but the middle doesn't let the bottom
+        assert_eq!((bottom.get(), middle.get(), top.get()), (0, 1, 1));
+        assert_eq!(StateMachine::first_updated(&sm.state_stack), 1);
+        assert_eq!(StateMachine::first_rendered(&sm.state_stack), 2);
+
+        sm.queue_pop();
+        sm.pre_update(&engine.context());
+        sm.update(&engine.context());
+        assert_eq!((bottom.get(), middle.get(), top.get()), (0, 2, 1));
+        assert_eq!(StateMachine::first_rendered(&sm.state_stack), 0);
+    }
+
+    #[test]
+    fn test_render() {
+        let engine = TestEngine::new();
+        let bottom = Rc::new(Cell::new(0));
+        let middle = Rc::new(Cell::new(0));
+        let top = Rc::new(Cell::new(0));
+
+        let mut sm = StateMachine::new();
+        sm.queue_push(OverlayState::boxed(false, false, &bottom));
+        sm.queue_push(OverlayState::boxed(false, true, &middle));
+        sm.queue_push(OverlayState::boxed(false, false, &top));
+        sm.pre_update(&engine.context());
+
+        assert_eq!(sm.render(&engine.context(), 0.5).len(), 1);
+        assert_eq!((bottom.get(), middle.get(), top.get()), (0, 0, 10));
+
+        sm.queue_pop();
+        sm.pre_update(&engine.context());
+        assert_eq!(sm.render(&engine.context(), 0.5).len(), 2);
+        assert_eq!((bottom.get(), middle.get(), top.get()), (10, 10, 10));
     }
 
     #[test]
     fn test_multi_push() {
         {
+            let engine = TestEngine::new();
             let mut sm = StateMachine::new();
             let ts0 = TestState::new(
                 1,
@@ -265,11 +761,12 @@ mod tests {
             );
 
             sm.queue_push(Box::new(ts0));
-            sm.pre_update();
+            sm.pre_update(&engine.context());
             sm.queue_push(Box::new(ts1));
-            sm.pre_update();
+            sm.pre_update(&engine.context());
         }
         {
+            let engine = TestEngine::new();
             let mut sm = StateMachine::new();
             let ts0 = TestState::new(
                 1,
@@ -294,10 +791,10 @@ mod tests {
 
             sm.queue_push(Box::new(ts0));
             sm.queue_push(Box::new(ts1));
-            sm.pre_update();
-            sm.update();
-            sm.update_fixed(0.0);
-            sm.post_update();
+            sm.pre_update(&engine.context());
+            sm.update(&engine.context());
+            sm.update_fixed(&engine.context(), 0.0);
+            sm.post_update(&engine.context());
         }
     }
 
@@ -21,6 +21,94 @@
  * A single input. Inputs are defined as an enum of an action that can be taken
 */
 
+pub mod action;
+pub mod keymap;
+pub mod poller;
+
+use serde::{ Serialize, Deserialize };
+
+/// A physical key on the keyboard
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Escape,
+    Backspace,
+    Tab,
+    BackTab,
+    Delete,
+    Insert,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    /// The centre key of the numpad when num lock is off
+    Begin,
+    F(u8)
+}
+
+/// Modifier keys held while a key or mouse button was pressed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub control: bool,
+    pub alt: bool
+}
+
+impl Modifiers {
+    pub fn none() -> Modifiers {
+        Modifiers::default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct KeyInput {
+    pub key: Key,
+    pub modifiers: Modifiers
+}
+
+impl KeyInput {
+    pub fn new(key: Key, modifiers: Modifiers) -> KeyInput {
+        KeyInput { key, modifiers }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MouseAction {
+    Press(MouseButton),
+    Release(MouseButton),
+    /// The mouse moved while the button was held
+    Drag(MouseButton),
+    Move,
+    ScrollUp,
+    ScrollDown
+}
+
+/// A mouse event at a given screen cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MouseInput {
+    pub action: MouseAction,
+    pub column: u16,
+    pub row: u16,
+    pub modifiers: Modifiers
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Input {
-    CloseGame
+    CloseGame,
+    Key(KeyInput),
+    Mouse(MouseInput),
+    /// The terminal was resized to the given number of columns and rows
+    Resize(u16, u16)
 }
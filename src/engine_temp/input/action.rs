@@ -0,0 +1,64 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use serde::{ Serialize, Deserialize };
+
+/// Something the player wants to do. Keys are mapped to actions through a keymap so
+/// states never have to care about which physical key was pressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveNorth,
+    MoveNorthEast,
+    MoveEast,
+    MoveSouthEast,
+    MoveSouth,
+    MoveSouthWest,
+    MoveWest,
+    MoveNorthWest,
+    Wait,
+    PickUp,
+    Drop,
+    Inventory,
+    Equipment,
+    Look,
+    Target,
+    Descend,
+    Ascend,
+    OpenMenu,
+    Quit,
+    MenuUp,
+    MenuDown,
+    MenuLeft,
+    MenuRight,
+    Confirm,
+    Cancel,
+    NextTarget,
+    PreviousTarget,
+    ToggleStatsOverlay,
+    ToggleConsole
+}
+
+/// The context an input is interpreted in. The same key can mean different things in
+/// different contexts
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputContext {
+    Gameplay,
+    Menu,
+    Targeting
+}
@@ -0,0 +1,448 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::engine_temp::input::{ Input, Key, KeyInput, Modifiers };
+use crate::engine_temp::input::action::{ Action, InputContext };
+use serde::{ Serialize, Deserialize };
+use thiserror::Error;
+use std::collections::{ BTreeMap, HashMap };
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Error, Debug)]
+pub enum KeymapError {
+    #[error("could not access keymap file")]
+    Io(#[from] std::io::Error),
+    #[error("keymap file is malformed")]
+    Format(#[from] serde_json::Error),
+    #[error("\"{0}\" is not a valid key")]
+    InvalidKey(String),
+    #[error("{key} is bound to both {first:?} and {second:?} in the {context:?} context")]
+    Conflict {
+        context: InputContext,
+        key: KeyInput,
+        first: Action,
+        second: Action
+    }
+}
+
+/// A set of default bindings to start from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeymapPreset {
+    /// Move with hjklyubn
+    ViKeys,
+    /// Move with the numpad, with or without num lock
+    Numpad,
+    /// Move with the arrow keys, with home/end/page up/page down for diagonals
+    Arrows
+}
+
+/// The on-disk representation of a keymap. Bindings are listed per action so a preset
+/// can be partially overridden: any action listed replaces all of the preset's keys for it
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeymapConfig {
+    #[serde(default)]
+    pub preset: Option<KeymapPreset>,
+    #[serde(default)]
+    pub bindings: BTreeMap<InputContext, BTreeMap<Action, Vec<String>>>
+}
+
+impl KeyInput {
+    /// Character keys already encode shift in their case, so we drop it to make "K" and
+    /// "shift+k" the same binding
+    fn normalised(&self) -> KeyInput {
+        let mut normalised = *self;
+        match normalised.key {
+            Key::Char(c) => {
+                if normalised.modifiers.shift && c.is_ascii_lowercase() {
+                    normalised.key = Key::Char(c.to_ascii_uppercase());
+                }
+                normalised.modifiers.shift = false;
+            },
+            Key::BackTab => normalised.modifiers.shift = false,
+            _ => {}
+        }
+        normalised
+    }
+}
+
+impl FromStr for KeyInput {
+    type Err = KeymapError;
+
+    /// Parse a key such as "k", "shift+up", "ctrl+s" or "f5". Modifiers are separated by "+"
+    fn from_str(s: &str) -> Result<KeyInput, KeymapError> {
+        let invalid = || KeymapError::InvalidKey(s.to_string());
+
+        // "+" on its own, or as the last part of a chord, is the plus key itself
+        let (modifier_part, key_part) = if s == "+" {
+            ("", "+")
+        } else if let Some(modifiers) = s.strip_suffix("++") {
+            (modifiers, "+")
+        } else {
+            match s.rsplit_once('+') {
+                Some((modifiers, key)) => (modifiers, key),
+                None => ("", s)
+            }
+        };
+
+        let mut modifiers = Modifiers::none();
+        for modifier in modifier_part.split('+').filter(|m| !m.is_empty()) {
+            match modifier.to_ascii_lowercase().as_str() {
+                "shift" => modifiers.shift = true,
+                "ctrl" | "control" => modifiers.control = true,
+                "alt" => modifiers.alt = true,
+                _ => return Err(invalid())
+            }
+        }
+
+        let mut chars = key_part.chars();
+        let key = match (chars.next(), chars.next()) {
+            (Some(c), None) => Key::Char(c),
+            _ => match key_part.to_ascii_lowercase().as_str() {
+                "space" => Key::Char(' '),
+                "enter" | "return" => Key::Enter,
+                "escape" | "esc" => Key::Escape,
+                "backspace" => Key::Backspace,
+                "tab" => Key::Tab,
+                "backtab" => Key::BackTab,
+                "delete" | "del" => Key::Delete,
+                "insert" | "ins" => Key::Insert,
+                "up" => Key::Up,
+                "down" => Key::Down,
+                "left" => Key::Left,
+                "right" => Key::Right,
+                "home" => Key::Home,
+                "end" => Key::End,
+                "pageup" | "pgup" => Key::PageUp,
+                "pagedown" | "pgdn" => Key::PageDown,
+                "begin" => Key::Begin,
+                function => {
+                    let number = function.strip_prefix('f')
+                        .and_then(|n| n.parse::<u8>().ok())
+                        .filter(|n| (1..=24).contains(n))
+                        .ok_or_else(invalid)?;
+                    Key::F(number)
+                }
+            }
+        };
+
+        Ok(KeyInput::new(key, modifiers).normalised())
+    }
+}
+
+impl fmt::Display for KeyInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.control {
+            write!(f, "ctrl+")?;
+        }
+        if self.modifiers.alt {
+            write!(f, "alt+")?;
+        }
+        if self.modifiers.shift {
+            write!(f, "shift+")?;
+        }
+
+        match self.key {
+            Key::Char(' ') => write!(f, "space"),
+            Key::Char(c) => write!(f, "{}", c),
+            Key::Enter => write!(f, "enter"),
+            Key::Escape => write!(f, "escape"),
+            Key::Backspace => write!(f, "backspace"),
+            Key::Tab => write!(f, "tab"),
+            Key::BackTab => write!(f, "backtab"),
+            Key::Delete => write!(f, "delete"),
+            Key::Insert => write!(f, "insert"),
+            Key::Up => write!(f, "up"),
+            Key::Down => write!(f, "down"),
+            Key::Left => write!(f, "left"),
+            Key::Right => write!(f, "right"),
+            Key::Home => write!(f, "home"),
+            Key::End => write!(f, "end"),
+            Key::PageUp => write!(f, "pageup"),
+            Key::PageDown => write!(f, "pagedown"),
+            Key::Begin => write!(f, "begin"),
+            Key::F(n) => write!(f, "f{}", n)
+        }
+    }
+}
+
+/// Bindings shared by every preset
+const COMMON_BINDINGS: &[(InputContext, Action, &str)] = &[
+    (InputContext::Gameplay, Action::PickUp, ","),
+    (InputContext::Gameplay, Action::PickUp, "g"),
+    (InputContext::Gameplay, Action::Drop, "d"),
+    (InputContext::Gameplay, Action::Inventory, "i"),
+    (InputContext::Gameplay, Action::Equipment, "e"),
+    (InputContext::Gameplay, Action::Look, "x"),
+    (InputContext::Gameplay, Action::Target, "f"),
+    (InputContext::Gameplay, Action::Descend, ">"),
+    (InputContext::Gameplay, Action::Ascend, "<"),
+    (InputContext::Gameplay, Action::OpenMenu, "escape"),
+    (InputContext::Gameplay, Action::Quit, "Q"),
+    (InputContext::Gameplay, Action::ToggleStatsOverlay, "f3"),
+    (InputContext::Gameplay, Action::ToggleConsole, "`"),
+
+    (InputContext::Menu, Action::MenuUp, "up"),
+    (InputContext::Menu, Action::MenuDown, "down"),
+    (InputContext::Menu, Action::MenuLeft, "left"),
+    (InputContext::Menu, Action::MenuRight, "right"),
+    (InputContext::Menu, Action::Confirm, "enter"),
+    (InputContext::Menu, Action::Cancel, "escape"),
+    (InputContext::Menu, Action::ToggleConsole, "`"),
+
+    (InputContext::Targeting, Action::Confirm, "enter"),
+    (InputContext::Targeting, Action::Confirm, "f"),
+    (InputContext::Targeting, Action::Cancel, "escape"),
+    (InputContext::Targeting, Action::NextTarget, "tab"),
+    (InputContext::Targeting, Action::PreviousTarget, "backtab"),
+];
+
+const VI_KEYS_BINDINGS: &[(Action, &str)] = &[
+    (Action::MoveNorth, "k"),
+    (Action::MoveNorthEast, "u"),
+    (Action::MoveEast, "l"),
+    (Action::MoveSouthEast, "n"),
+    (Action::MoveSouth, "j"),
+    (Action::MoveSouthWest, "b"),
+    (Action::MoveWest, "h"),
+    (Action::MoveNorthWest, "y"),
+    (Action::Wait, "."),
+];
+
+const NUMPAD_BINDINGS: &[(Action, &str)] = &[
+    // With num lock on
+    (Action::MoveNorth, "8"),
+    (Action::MoveNorthEast, "9"),
+    (Action::MoveEast, "6"),
+    (Action::MoveSouthEast, "3"),
+    (Action::MoveSouth, "2"),
+    (Action::MoveSouthWest, "1"),
+    (Action::MoveWest, "4"),
+    (Action::MoveNorthWest, "7"),
+    (Action::Wait, "5"),
+    // With num lock off
+    (Action::MoveNorth, "up"),
+    (Action::MoveNorthEast, "pageup"),
+    (Action::MoveEast, "right"),
+    (Action::MoveSouthEast, "pagedown"),
+    (Action::MoveSouth, "down"),
+    (Action::MoveSouthWest, "end"),
+    (Action::MoveWest, "left"),
+    (Action::MoveNorthWest, "home"),
+    (Action::Wait, "begin"),
+];
+
+const ARROWS_BINDINGS: &[(Action, &str)] = &[
+    (Action::MoveNorth, "up"),
+    (Action::MoveNorthEast, "pageup"),
+    (Action::MoveEast, "right"),
+    (Action::MoveSouthEast, "pagedown"),
+    (Action::MoveSouth, "down"),
+    (Action::MoveSouthWest, "end"),
+    (Action::MoveWest, "left"),
+    (Action::MoveNorthWest, "home"),
+    (Action::Wait, "."),
+    (Action::Wait, "begin"),
+];
+
+/// Maps physical keys to actions for each input context
+#[derive(Clone, Debug, Default)]
+pub struct Keymap {
+    bindings: HashMap<InputContext, HashMap<KeyInput, Action>>
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap::default()
+    }
+
+    pub fn preset(preset: KeymapPreset) -> Keymap {
+        let movement = match preset {
+            KeymapPreset::ViKeys => VI_KEYS_BINDINGS,
+            KeymapPreset::Numpad => NUMPAD_BINDINGS,
+            KeymapPreset::Arrows => ARROWS_BINDINGS
+        };
+
+        let movement_bindings = movement.iter()
+            .flat_map(|(action, key)| {
+                [InputContext::Gameplay, InputContext::Targeting].map(|context| (context, *action, *key))
+            });
+
+        let mut keymap = Keymap::new();
+        for (context, action, key) in movement_bindings.chain(COMMON_BINDINGS.iter().copied()) {
+            keymap.bind(context, key.parse().unwrap(), action)
+                .expect("Keymap presets should not have conflicting bindings");
+        }
+        keymap
+    }
+
+    pub fn from_config(config: &KeymapConfig) -> Result<Keymap, KeymapError> {
+        let mut keymap = match config.preset {
+            Some(preset) => Keymap::preset(preset),
+            None => Keymap::new()
+        };
+
+        // Unbind everything we are overriding first so a rebinding can take a key from
+        // another overridden action without a conflict
+        for (context, actions) in config.bindings.iter() {
+            for action in actions.keys() {
+                keymap.unbind_action(*context, *action);
+            }
+        }
+
+        for (context, actions) in config.bindings.iter() {
+            for (action, keys) in actions.iter() {
+                for key in keys.iter() {
+                    keymap.bind(*context, key.parse()?, *action)?;
+                }
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    pub fn load<P>(path: P) -> Result<Keymap, KeymapError> where
+        P: AsRef<Path> {
+        let reader = BufReader::new(File::open(path)?);
+        let config: KeymapConfig = serde_json::from_reader(reader)?;
+        Keymap::from_config(&config)
+    }
+
+    /// Bind a key to an action. A key can only be bound to a single action in a context
+    pub fn bind(&mut self, context: InputContext, key: KeyInput, action: Action) -> Result<(), KeymapError> {
+        let key = key.normalised();
+        let keys = self.bindings.entry(context).or_default();
+        match keys.get(&key) {
+            Some(existing) if *existing != action => Err(KeymapError::Conflict {
+                context,
+                key,
+                first: *existing,
+                second: action
+            }),
+            _ => {
+                keys.insert(key, action);
+                Ok(())
+            }
+        }
+    }
+
+    /// Remove every key bound to an action
+    pub fn unbind_action(&mut self, context: InputContext, action: Action) {
+        if let Some(keys) = self.bindings.get_mut(&context) {
+            keys.retain(|_, bound| *bound != action);
+        }
+    }
+
+    pub fn action(&self, context: InputContext, key: &KeyInput) -> Option<Action> {
+        self.bindings.get(&context)?.get(&key.normalised()).copied()
+    }
+
+    /// Get the action an input maps to in the given context, if any
+    pub fn translate(&self, context: InputContext, input: &Input) -> Option<Action> {
+        match input {
+            Input::Key(key) => self.action(context, key),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(s: &str) -> KeyInput {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(key("k"), KeyInput::new(Key::Char('k'), Modifiers::none()));
+        assert_eq!(key("K"), key("shift+k"));
+        assert_eq!(key("ctrl+s"), KeyInput::new(Key::Char('s'), Modifiers { shift: false, control: true, alt: false }));
+        assert_eq!(key("Shift+Up"), KeyInput::new(Key::Up, Modifiers { shift: true, control: false, alt: false }));
+        assert_eq!(key("f12"), KeyInput::new(Key::F(12), Modifiers::none()));
+        assert_eq!(key("+"), KeyInput::new(Key::Char('+'), Modifiers::none()));
+        assert_eq!(key("alt++"), KeyInput::new(Key::Char('+'), Modifiers { shift: false, control: false, alt: true }));
+
+        assert!("hyper+k".parse::<KeyInput>().is_err());
+        assert!("f99".parse::<KeyInput>().is_err());
+        assert!("nothing".parse::<KeyInput>().is_err());
+
+        for s in ["ctrl+alt+delete", "space", "backtab", "pageup", "Q", "f3"] {
+            assert_eq!(key(&key(s).to_string()), key(s));
+        }
+    }
+
+    #[test]
+    fn test_presets() {
+        for preset in [KeymapPreset::ViKeys, KeymapPreset::Numpad, KeymapPreset::Arrows] {
+            let keymap = Keymap::preset(preset);
+            assert_eq!(keymap.action(InputContext::Menu, &key("escape")), Some(Action::Cancel));
+            assert_eq!(keymap.action(InputContext::Gameplay, &key("escape")), Some(Action::OpenMenu));
+        }
+
+        let vi = Keymap::preset(KeymapPreset::ViKeys);
+        assert_eq!(vi.action(InputContext::Gameplay, &key("y")), Some(Action::MoveNorthWest));
+        assert_eq!(vi.translate(InputContext::Targeting, &Input::Key(key("j"))), Some(Action::MoveSouth));
+        assert_eq!(vi.translate(InputContext::Gameplay, &Input::CloseGame), None);
+
+        let numpad = Keymap::preset(KeymapPreset::Numpad);
+        assert_eq!(numpad.action(InputContext::Gameplay, &key("9")), Some(Action::MoveNorthEast));
+        assert_eq!(numpad.action(InputContext::Gameplay, &key("pageup")), Some(Action::MoveNorthEast));
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let mut keymap = Keymap::new();
+        keymap.bind(InputContext::Gameplay, key("k"), Action::MoveNorth).unwrap();
+        keymap.bind(InputContext::Gameplay, key("k"), Action::MoveNorth).unwrap();
+        keymap.bind(InputContext::Menu, key("k"), Action::MenuUp).unwrap();
+        assert!(matches!(
+            keymap.bind(InputContext::Gameplay, key("k"), Action::Inventory),
+            Err(KeymapError::Conflict { first: Action::MoveNorth, second: Action::Inventory, .. })
+        ));
+    }
+
+    #[test]
+    fn test_config() {
+        let config: KeymapConfig = serde_json::from_str(r#"{
+            "preset": "vi-keys",
+            "bindings": {
+                "gameplay": {
+                    "Inventory": ["tab", "I"],
+                    "Drop": ["i"]
+                }
+            }
+        }"#).unwrap();
+        let keymap = Keymap::from_config(&config).unwrap();
+        assert_eq!(keymap.action(InputContext::Gameplay, &key("i")), Some(Action::Drop));
+        assert_eq!(keymap.action(InputContext::Gameplay, &key("shift+i")), Some(Action::Inventory));
+        assert_eq!(keymap.action(InputContext::Gameplay, &key("d")), None);
+        assert_eq!(keymap.action(InputContext::Gameplay, &key("k")), Some(Action::MoveNorth));
+
+        let config: KeymapConfig = serde_json::from_str(r#"{
+            "preset": "vi-keys",
+            "bindings": { "gameplay": { "Inventory": ["k"] } }
+        }"#).unwrap();
+        assert!(matches!(Keymap::from_config(&config), Err(KeymapError::Conflict { .. })));
+    }
+}
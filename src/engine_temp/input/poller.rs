@@ -0,0 +1,217 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::engine_temp::input::{
+    Input, Key, KeyInput, Modifiers, MouseAction, MouseButton, MouseInput
+};
+use crossterm::event::{ self, Event, KeyCode, KeyEventKind, KeyModifiers, MouseEventKind };
+use std::sync::{ Arc, mpsc, atomic::{ AtomicBool, Ordering } };
+use std::thread;
+use std::time::Duration;
+
+/// How long the poll thread waits for an event before checking if it should stop
+const POLL_TIMEOUT: Duration = Duration::from_millis(50);
+
+fn translate_modifiers(modifiers: KeyModifiers) -> Modifiers {
+    Modifiers {
+        shift: modifiers.contains(KeyModifiers::SHIFT),
+        control: modifiers.contains(KeyModifiers::CONTROL),
+        alt: modifiers.contains(KeyModifiers::ALT)
+    }
+}
+
+fn translate_key(code: KeyCode) -> Option<Key> {
+    Some(match code {
+        KeyCode::Char(c) => Key::Char(c),
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Esc => Key::Escape,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Tab => Key::Tab,
+        KeyCode::BackTab => Key::BackTab,
+        KeyCode::Delete => Key::Delete,
+        KeyCode::Insert => Key::Insert,
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        KeyCode::Left => Key::Left,
+        KeyCode::Right => Key::Right,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::KeypadBegin => Key::Begin,
+        KeyCode::F(n) => Key::F(n),
+        _ => return None
+    })
+}
+
+fn translate_mouse_button(button: event::MouseButton) -> MouseButton {
+    match button {
+        event::MouseButton::Left => MouseButton::Left,
+        event::MouseButton::Right => MouseButton::Right,
+        event::MouseButton::Middle => MouseButton::Middle
+    }
+}
+
+/// Convert a crossterm event into an engine input. Events the engine doesn't care about
+/// are dropped
+fn translate(event: Event) -> Option<Input> {
+    match event {
+        Event::Key(key_event) => {
+            if key_event.kind == KeyEventKind::Release {
+                return None
+            }
+
+            let modifiers = translate_modifiers(key_event.modifiers);
+            // Raw mode swallows the interrupt signal so we have to handle it ourselves
+            if modifiers.control && key_event.code == KeyCode::Char('c') {
+                return Some(Input::CloseGame)
+            }
+
+            translate_key(key_event.code).map(|key| Input::Key(KeyInput::new(key, modifiers)))
+        },
+        Event::Mouse(mouse_event) => {
+            let action = match mouse_event.kind {
+                MouseEventKind::Down(button) => MouseAction::Press(translate_mouse_button(button)),
+                MouseEventKind::Up(button) => MouseAction::Release(translate_mouse_button(button)),
+                MouseEventKind::Drag(button) => MouseAction::Drag(translate_mouse_button(button)),
+                MouseEventKind::Moved => MouseAction::Move,
+                MouseEventKind::ScrollUp => MouseAction::ScrollUp,
+                MouseEventKind::ScrollDown => MouseAction::ScrollDown
+            };
+
+            Some(Input::Mouse(MouseInput {
+                action,
+                column: mouse_event.column,
+                row: mouse_event.row,
+                modifiers: translate_modifiers(mouse_event.modifiers)
+            }))
+        },
+        Event::Resize(columns, rows) => Some(Input::Resize(columns, rows)),
+        _ => None
+    }
+}
+
+/// Polls the terminal for keyboard, mouse and resize events on its own thread and sends
+/// them to the engine.
+///
+/// The terminal should be set up with a `TerminalGuard` first, otherwise key presses
+/// are line buffered and mouse events are never reported
+pub struct InputPoller {
+    running: Arc<AtomicBool>,
+    poll_thread: Option<thread::JoinHandle<()>>
+}
+
+impl InputPoller {
+    pub fn new(input_queue: mpsc::Sender<Input>) -> InputPoller {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let poll_thread = thread::spawn(move || {
+            while thread_running.load(Ordering::SeqCst) {
+                match event::poll(POLL_TIMEOUT) {
+                    Ok(true) => {},
+                    Ok(false) => continue,
+                    Err(e) => {
+                        log::error!("Failed to poll terminal events: {}", e);
+                        break;
+                    }
+                }
+
+                let input = match event::read() {
+                    Ok(event) => translate(event),
+                    Err(e) => {
+                        log::error!("Failed to read terminal event: {}", e);
+                        break;
+                    }
+                };
+
+                if let Some(input) = input {
+                    // If the engine has gone away nobody is listening
+                    if input_queue.send(input).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        InputPoller {
+            running,
+            poll_thread: Some(poll_thread)
+        }
+    }
+
+    /// Stop polling for input and wait for the poll thread to finish
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(poll_thread) = self.poll_thread.take() {
+            if poll_thread.join().is_err() {
+                log::error!("Input poll thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for InputPoller {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{ KeyEvent, MouseEvent };
+
+    #[test]
+    fn test_translate_key() {
+        let event = Event::Key(KeyEvent::new(KeyCode::Char('k'), KeyModifiers::NONE));
+        assert_eq!(translate(event), Some(Input::Key(KeyInput::new(Key::Char('k'), Modifiers::none()))));
+
+        let event = Event::Key(KeyEvent::new(KeyCode::Up, KeyModifiers::SHIFT | KeyModifiers::ALT));
+        let modifiers = Modifiers { shift: true, control: false, alt: true };
+        assert_eq!(translate(event), Some(Input::Key(KeyInput::new(Key::Up, modifiers))));
+
+        let event = Event::Key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL));
+        assert_eq!(translate(event), Some(Input::CloseGame));
+
+        let mut event = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        event.kind = KeyEventKind::Release;
+        assert_eq!(translate(Event::Key(event)), None);
+
+        let event = Event::Key(KeyEvent::new(KeyCode::CapsLock, KeyModifiers::NONE));
+        assert_eq!(translate(event), None);
+    }
+
+    #[test]
+    fn test_translate_mouse_and_resize() {
+        let event = Event::Mouse(MouseEvent {
+            kind: MouseEventKind::Down(event::MouseButton::Right),
+            column: 4,
+            row: 9,
+            modifiers: KeyModifiers::CONTROL
+        });
+        assert_eq!(translate(event), Some(Input::Mouse(MouseInput {
+            action: MouseAction::Press(MouseButton::Right),
+            column: 4,
+            row: 9,
+            modifiers: Modifiers { shift: false, control: true, alt: false }
+        })));
+
+        assert_eq!(translate(Event::Resize(80, 24)), Some(Input::Resize(80, 24)));
+        assert_eq!(translate(Event::FocusGained), None);
+    }
+}
@@ -0,0 +1,321 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use log::{ Level, LevelFilter, Log, Metadata, Record };
+use simplelog::{ Config, SharedLogger };
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Mutex };
+
+/// A log file that is moved aside once it grows past a size. Old files are kept as
+/// `<path>.1`, `<path>.2` and so on, with `.1` being the most recent, up to `max_files`
+/// of them. Files are only rotated at the end of a line so a message is never split
+/// between two files
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    max_files: usize
+}
+
+impl RotatingFile {
+    /// Open a log file. Anything left over from the last run is rotated out first so
+    /// every run starts with a fresh file
+    pub fn open<P>(path: P, max_bytes: u64, max_files: usize) -> io::Result<RotatingFile> where
+        P: Into<PathBuf> {
+        let path = path.into();
+        if fs::metadata(&path).map(|metadata| metadata.len() > 0).unwrap_or(false) {
+            RotatingFile::rotate_files(&path, max_files)?;
+        }
+
+        Ok(RotatingFile {
+            file: RotatingFile::create(&path)?,
+            path,
+            size: 0,
+            max_bytes,
+            max_files
+        })
+    }
+
+    fn create(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).write(true).truncate(true).open(path)
+    }
+
+    fn numbered(path: &Path, number: usize) -> PathBuf {
+        let mut numbered = OsString::from(path.as_os_str());
+        numbered.push(format!(".{}", number));
+        PathBuf::from(numbered)
+    }
+
+    /// Shift every old file up by one, dropping the oldest, and move the current file to `.1`
+    fn rotate_files(path: &Path, max_files: usize) -> io::Result<()> {
+        if max_files == 0 {
+            return fs::remove_file(path);
+        }
+
+        let oldest = RotatingFile::numbered(path, max_files);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for number in (1..max_files).rev() {
+            let from = RotatingFile::numbered(path, number);
+            if from.exists() {
+                fs::rename(from, RotatingFile::numbered(path, number + 1))?;
+            }
+        }
+        fs::rename(path, RotatingFile::numbered(path, 1))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        RotatingFile::rotate_files(&self.path, self.max_files)?;
+        self.file = RotatingFile::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Rotate at the first line end that reaches the limit, wherever it falls in the
+        // write, so a line is never split between files
+        let remaining = self.max_bytes.saturating_sub(self.size) as usize;
+        let line_end = buf.iter()
+            .enumerate()
+            .skip(remaining.saturating_sub(1))
+            .find(|(_, byte)| **byte == b'\n')
+            .map(|(index, _)| index + 1);
+
+        let written = self.file.write(&buf[..line_end.unwrap_or(buf.len())])?;
+        self.size += written as u64;
+        if Some(written) == line_end {
+            self.rotate()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// A log message kept in memory
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogRecord {
+    pub level: Level,
+    pub module: String,
+    pub message: String
+}
+
+/// Which records to show. A record matches if it is at or above the level and its module
+/// contains the module text
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogFilter {
+    pub level: LevelFilter,
+    pub module: String
+}
+
+impl Default for LogFilter {
+    fn default() -> LogFilter {
+        LogFilter {
+            level: LevelFilter::Trace,
+            module: String::new()
+        }
+    }
+}
+
+impl LogFilter {
+    pub fn matches(&self, record: &LogRecord) -> bool {
+        record.level <= self.level && record.module.contains(&self.module)
+    }
+}
+
+/// The most recent log records, shared between the logger and anything that wants to
+/// display them. Once full, the oldest record is dropped for every new one
+#[derive(Clone)]
+pub struct LogBuffer {
+    records: Arc<Mutex<VecDeque<LogRecord>>>,
+    capacity: usize
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> LogBuffer {
+        LogBuffer {
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity
+        }
+    }
+
+    pub fn push(&self, record: LogRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut records = self.records.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Every record that matches the filter, oldest first
+    pub fn records(&self, filter: &LogFilter) -> Vec<LogRecord> {
+        let records = self.records.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        records.iter()
+            .filter(|record| filter.matches(record))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Logs into a `LogBuffer`. Meant to be combined with the file logger through simplelog's
+/// `CombinedLogger`
+pub struct BufferLogger {
+    level: LevelFilter,
+    buffer: LogBuffer
+}
+
+impl BufferLogger {
+    pub fn new(level: LevelFilter, buffer: LogBuffer) -> Box<BufferLogger> {
+        Box::new(BufferLogger {
+            level,
+            buffer
+        })
+    }
+}
+
+impl Log for BufferLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        self.buffer.push(LogRecord {
+            level: record.level(),
+            module: record.module_path().unwrap_or(record.target()).to_string(),
+            message: record.args().to_string()
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for BufferLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(level: Level, module: &str, message: &str) -> LogRecord {
+        LogRecord { level, module: module.to_string(), message: message.to_string() }
+    }
+
+    #[test]
+    fn test_rotation() {
+        let directory = std::env::temp_dir().join(format!("roguelike_logging_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test.log");
+        fs::write(&path, "last run\n").unwrap();
+
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        assert_eq!(fs::read_to_string(RotatingFile::numbered(&path, 1)).unwrap(), "last run\n");
+
+        // a line is never split, even if it goes past the limit
+        file.write_all(b"first ").unwrap();
+        file.write_all(b"line\n").unwrap();
+        file.write_all(b"second line\n").unwrap();
+        file.write_all(b"third\n").unwrap();
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(RotatingFile::numbered(&path, 1)).unwrap(), "second line\n");
+        assert_eq!(fs::read_to_string(RotatingFile::numbered(&path, 2)).unwrap(), "first line\n");
+        assert!(!RotatingFile::numbered(&path, 3).exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_rotation_mid_write() {
+        // writes don't have to end on a line for the file to rotate
+        let directory = std::env::temp_dir().join(format!("roguelike_logging_mid_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test.log");
+
+        let mut file = RotatingFile::open(&path, 10, 3).unwrap();
+        file.write_all(b"short\nlong enough line\nnext").unwrap();
+        file.write_all(b" line\nmore").unwrap();
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "more");
+        assert_eq!(fs::read_to_string(RotatingFile::numbered(&path, 1)).unwrap(), "next line\n");
+        assert_eq!(fs::read_to_string(RotatingFile::numbered(&path, 2)).unwrap(), "short\nlong enough line\n");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let buffer = LogBuffer::new(3);
+        buffer.push(record(Level::Info, "roguelike::engine", "one"));
+        buffer.push(record(Level::Debug, "roguelike::engine", "two"));
+        buffer.push(record(Level::Warn, "roguelike::game", "three"));
+        buffer.push(record(Level::Error, "roguelike::renderer", "four"));
+
+        let messages = |filter: &LogFilter| -> Vec<String> {
+            buffer.records(filter).into_iter().map(|record| record.message).collect()
+        };
+        assert_eq!(messages(&LogFilter::default()), vec!["two", "three", "four"]);
+        assert_eq!(messages(&LogFilter { level: LevelFilter::Warn, module: String::new() }), vec!["three", "four"]);
+        assert_eq!(messages(&LogFilter { level: LevelFilter::Trace, module: "engine".to_string() }), vec!["two"]);
+    }
+
+    #[test]
+    fn test_buffer_logger() {
+        let buffer = LogBuffer::new(8);
+        let logger = BufferLogger::new(LevelFilter::Info, buffer.clone());
+        logger.log(&Record::builder()
+            .level(Level::Info)
+            .module_path(Some("roguelike::game"))
+            .args(format_args!("hello {}", 1))
+            .build());
+        logger.log(&Record::builder().level(Level::Debug).args(format_args!("hidden")).build());
+
+        assert_eq!(buffer.records(&LogFilter::default()), vec![record(Level::Info, "roguelike::game", "hello 1")]);
+    }
+}
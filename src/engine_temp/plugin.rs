@@ -0,0 +1,46 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::engine_temp::engine::EngineEvent;
+use crate::engine_temp::game::context::EngineContext;
+use crate::engine_temp::input::Input;
+use crate::renderer::crossterm::command_buffer::CommandBuffer;
+
+/// A subsystem that hooks into the engine.
+///
+/// Plugins are called in the order they were registered, at startup, at each phase of
+/// every tick, and at shutdown. They see the same engine context states do, so they can
+/// publish engine events but cannot touch the state stack directly
+pub trait Plugin {
+    fn name(&self) -> &str;
+
+    /// Called once before the first tick
+    fn on_startup(&mut self, _ctx: &EngineContext) {}
+    /// Called with every input the engine receives, before it is given to any state
+    fn on_input(&mut self, _ctx: &EngineContext, _input: &Input) {}
+    /// Called before states are updated
+    fn pre_update(&mut self, _ctx: &EngineContext) {}
+    /// Called after states are updated
+    fn post_update(&mut self, _ctx: &EngineContext) {}
+    /// Called with every engine event before the engine handles it
+    fn on_engine_event(&mut self, _ctx: &EngineContext, _event: &EngineEvent) {}
+    /// Record draw commands. Plugins are drawn on top of every state
+    fn render(&mut self, _ctx: &EngineContext, _commands: &mut CommandBuffer) {}
+    /// Called once after the last tick
+    fn on_shutdown(&mut self, _ctx: &EngineContext) {}
+}
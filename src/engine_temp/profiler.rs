@@ -0,0 +1,172 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::VecDeque;
+use std::time::{ Duration, Instant };
+
+/// How long each phase of a single frame took
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTimings {
+    /// Time spent in the variable update, including pre and post update
+    pub update: Duration,
+    /// Time spent running every fixed update this frame
+    pub update_fixed: Duration,
+    pub render: Duration,
+    /// Time from the start of the last frame to the start of this one
+    pub frame: Duration,
+    pub fixed_steps: u32,
+    /// If simulation time was dropped this frame because we fell too far behind
+    pub dropped: bool
+}
+
+/// A summary of the frames in the profiler's window
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    pub frame_count: usize,
+    pub frames_per_second: f64,
+    pub average_update: Duration,
+    pub average_update_fixed: Duration,
+    pub average_render: Duration,
+    pub average_frame: Duration,
+    pub worst_frame: Duration,
+    pub average_fixed_steps: f64,
+    pub dropped_frames: usize
+}
+
+/// Keeps the timings of the last few frames
+pub struct Profiler {
+    frames: VecDeque<FrameTimings>,
+    window_size: usize,
+    last_frame: Option<Instant>
+}
+
+impl Profiler {
+    pub fn new(window_size: usize) -> Profiler {
+        Profiler {
+            frames: VecDeque::with_capacity(window_size),
+            window_size: usize::max(window_size, 1),
+            last_frame: None
+        }
+    }
+
+    /// Record a finished frame, measuring the frame time since the last one was recorded
+    pub fn record(&mut self, mut timings: FrameTimings) {
+        let now = Instant::now();
+        timings.frame = self.last_frame.map(|last| now - last).unwrap_or_default();
+        self.last_frame = Some(now);
+        self.push(timings);
+    }
+
+    fn push(&mut self, timings: FrameTimings) {
+        if self.frames.len() == self.window_size {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(timings);
+    }
+
+    /// The most recently recorded frame
+    pub fn last(&self) -> Option<&FrameTimings> {
+        self.frames.back()
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        let frame_count = self.frames.len();
+        if frame_count == 0 {
+            return FrameStats::default()
+        }
+
+        let total = self.frames.iter().fold(FrameTimings::default(), |total, frame| FrameTimings {
+            update: total.update + frame.update,
+            update_fixed: total.update_fixed + frame.update_fixed,
+            render: total.render + frame.render,
+            frame: total.frame + frame.frame,
+            fixed_steps: total.fixed_steps + frame.fixed_steps,
+            dropped: false
+        });
+
+        let frames_per_second = if total.frame.is_zero() {
+            0.0
+        } else {
+            frame_count as f64 / total.frame.as_secs_f64()
+        };
+
+        let count = frame_count as u32;
+        FrameStats {
+            frame_count,
+            frames_per_second,
+            average_update: total.update / count,
+            average_update_fixed: total.update_fixed / count,
+            average_render: total.render / count,
+            average_frame: total.frame / count,
+            worst_frame: self.frames.iter().map(|frame| frame.frame).max().unwrap_or_default(),
+            average_fixed_steps: total.fixed_steps as f64 / frame_count as f64,
+            dropped_frames: self.frames.iter().filter(|frame| frame.dropped).count()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(frame_ms: u64, fixed_steps: u32, dropped: bool) -> FrameTimings {
+        FrameTimings {
+            update: Duration::from_millis(1),
+            update_fixed: Duration::from_millis(2 * fixed_steps as u64),
+            render: Duration::from_millis(3),
+            frame: Duration::from_millis(frame_ms),
+            fixed_steps,
+            dropped
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(Profiler::new(10).stats(), FrameStats::default());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut profiler = Profiler::new(4);
+        profiler.push(frame(10, 1, false));
+        profiler.push(frame(30, 3, true));
+
+        let stats = profiler.stats();
+        assert_eq!(stats.frame_count, 2);
+        assert_eq!(stats.frames_per_second, 50.0);
+        assert_eq!(stats.average_frame, Duration::from_millis(20));
+        assert_eq!(stats.worst_frame, Duration::from_millis(30));
+        assert_eq!(stats.average_update_fixed, Duration::from_millis(4));
+        assert_eq!(stats.average_fixed_steps, 2.0);
+        assert_eq!(stats.dropped_frames, 1);
+    }
+
+    #[test]
+    fn test_rolling_window() {
+        let mut profiler = Profiler::new(2);
+        profiler.push(frame(100, 0, true));
+        profiler.push(frame(10, 1, false));
+        profiler.push(frame(10, 1, false));
+
+        let stats = profiler.stats();
+        assert_eq!(stats.frame_count, 2);
+        assert_eq!(stats.worst_frame, Duration::from_millis(10));
+        assert_eq!(stats.dropped_frames, 0);
+        assert_eq!(profiler.last(), Some(&frame(10, 1, false)));
+    }
+}
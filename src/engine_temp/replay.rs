@@ -0,0 +1,245 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::engine_temp::input::Input;
+use crate::engine_temp::game::context::EngineContext;
+use crate::engine_temp::plugin::Plugin;
+use serde::{ Serialize, Deserialize };
+use thiserror::Error;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{ BufReader, BufWriter, Write };
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("could not access replay file")]
+    Io(#[from] std::io::Error),
+    #[error("replay file is malformed")]
+    Format(#[from] serde_json::Error)
+}
+
+/// An input along with the engine tick it was consumed on
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedInput {
+    pub tick: u64,
+    pub input: Input
+}
+
+/// Everything needed to reproduce a run: every input the engine consumed, in order, and
+/// how long each engine tick took. Playing the tick times back in place of the clock runs
+/// the same fixed updates with the same inputs as the recorded run. The seed is kept so
+/// anything reading it from the engine context sees the same value
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    pub inputs: Vec<RecordedInput>,
+    /// The time each tick advanced by, in nanoseconds. Replays without it are played
+    /// back against the clock
+    #[serde(default)]
+    pub delta_times: Vec<u64>
+}
+
+impl Replay {
+    pub fn new(seed: u64) -> Replay {
+        Replay {
+            seed,
+            inputs: Vec::new(),
+            delta_times: Vec::new()
+        }
+    }
+
+    pub fn load<P>(path: P) -> Result<Replay, ReplayError> where
+        P: AsRef<Path> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<(), ReplayError> where
+        P: AsRef<Path> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Records inputs and tick times as the engine consumes them. The replay is written when the engine
+/// shuts down, or when the recorder is dropped so a run that ends in a panic is still saved
+pub struct ReplayRecorder {
+    path: PathBuf,
+    replay: Replay,
+    saved: bool
+}
+
+impl ReplayRecorder {
+    pub fn new<P>(path: P) -> ReplayRecorder where
+        P: Into<PathBuf> {
+        ReplayRecorder {
+            path: path.into(),
+            replay: Replay::new(0),
+            saved: false
+        }
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.replay.seed = seed;
+    }
+
+    pub fn record(&mut self, tick: u64, input: &Input) {
+        self.replay.inputs.push(RecordedInput { tick, input: input.clone() });
+    }
+
+    pub fn record_tick(&mut self, delta_time: Duration) {
+        self.replay.delta_times.push(delta_time.as_nanos().min(u64::MAX as u128) as u64);
+    }
+
+    fn save(&mut self) {
+        match self.replay.save(&self.path) {
+            Ok(_) => log::info!("Saved replay to {}", self.path.display()),
+            Err(e) => log::error!("Failed to save replay to {}: {}", self.path.display(), e)
+        }
+        self.saved = true;
+    }
+}
+
+impl Plugin for ReplayRecorder {
+    fn name(&self) -> &str {
+        "replay_recorder"
+    }
+
+    fn on_startup(&mut self, ctx: &EngineContext) {
+        self.set_seed(ctx.seed);
+    }
+
+    fn on_input(&mut self, ctx: &EngineContext, input: &Input) {
+        self.record(ctx.time.tick, input);
+    }
+
+    fn pre_update(&mut self, ctx: &EngineContext) {
+        self.record_tick(ctx.time.delta_time);
+    }
+
+    fn on_shutdown(&mut self, _ctx: &EngineContext) {
+        self.save();
+    }
+}
+
+impl Drop for ReplayRecorder {
+    fn drop(&mut self) {
+        if !self.saved {
+            self.save();
+        }
+    }
+}
+
+/// Plays back recorded inputs in place of live input
+pub struct ReplayPlayer {
+    inputs: VecDeque<RecordedInput>,
+    delta_times: VecDeque<u64>
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> ReplayPlayer {
+        ReplayPlayer {
+            inputs: replay.inputs.into(),
+            delta_times: replay.delta_times.into()
+        }
+    }
+
+    /// Get the next input that was consumed on or before the given tick
+    pub fn next(&mut self, tick: u64) -> Option<Input> {
+        if self.inputs.front()?.tick <= tick {
+            return self.inputs.pop_front().map(|recorded| recorded.input)
+        }
+        None
+    }
+
+    /// How long the next tick took when it was recorded
+    pub fn next_delta_time(&mut self) -> Option<Duration> {
+        self.delta_times.pop_front().map(Duration::from_nanos)
+    }
+
+    /// If every input and tick time has been played back
+    pub fn is_finished(&self) -> bool {
+        self.inputs.is_empty() && self.delta_times.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("replay_test_{}.json", std::process::id()));
+        {
+            let mut recorder = ReplayRecorder::new(&path);
+            recorder.set_seed(1234);
+            recorder.record(3, &Input::CloseGame);
+            recorder.record(7, &Input::CloseGame);
+            recorder.record_tick(Duration::from_millis(16));
+        }
+
+        let replay = Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(replay.seed, 1234);
+        assert_eq!(replay.inputs, vec![
+            RecordedInput { tick: 3, input: Input::CloseGame },
+            RecordedInput { tick: 7, input: Input::CloseGame }
+        ]);
+        assert_eq!(replay.delta_times, vec![16_000_000]);
+    }
+
+    #[test]
+    fn test_playback() {
+        let mut replay = Replay::new(0);
+        replay.inputs.push(RecordedInput { tick: 2, input: Input::CloseGame });
+        replay.inputs.push(RecordedInput { tick: 2, input: Input::CloseGame });
+        replay.inputs.push(RecordedInput { tick: 5, input: Input::CloseGame });
+
+        let mut player = ReplayPlayer::new(replay);
+        assert_eq!(player.next(0), None);
+        assert_eq!(player.next(1), None);
+        assert_eq!(player.next(2), Some(Input::CloseGame));
+        assert_eq!(player.next(2), Some(Input::CloseGame));
+        assert_eq!(player.next(2), None);
+        assert!(!player.is_finished());
+        assert_eq!(player.next(5), Some(Input::CloseGame));
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn test_tick_playback() {
+        let mut replay = Replay::new(0);
+        replay.delta_times = vec![5, 10];
+
+        // old replays without tick times still load
+        let json = r#"{ "seed": 3, "inputs": [] }"#;
+        assert_eq!(serde_json::from_str::<Replay>(json).unwrap(), Replay::new(3));
+
+        let mut player = ReplayPlayer::new(replay);
+        assert!(!player.is_finished());
+        assert_eq!(player.next_delta_time(), Some(Duration::from_nanos(5)));
+        assert_eq!(player.next_delta_time(), Some(Duration::from_nanos(10)));
+        assert_eq!(player.next_delta_time(), None);
+        assert!(player.is_finished());
+    }
+}
@@ -0,0 +1,102 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::engine_temp::engine::EngineEvent;
+use crate::engine_temp::game::context::EngineContext;
+use crate::engine_temp::plugin::Plugin;
+use crate::renderer::crossterm::command::{ Command, DrawInfo };
+use crate::renderer::crossterm::command_buffer::CommandBuffer;
+use crate::renderer::colours::Colour;
+use std::time::Duration;
+
+pub const STATS_OVERLAY_NAME: &str = "stats";
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Draws the profiler's frame statistics in the top left corner of the screen. Toggled
+/// with `EngineEvent::ToggleDebugOverlay("stats")`
+pub struct StatsOverlay {
+    visible: bool
+}
+
+impl StatsOverlay {
+    pub fn new() -> StatsOverlay {
+        StatsOverlay {
+            visible: false
+        }
+    }
+}
+
+impl Plugin for StatsOverlay {
+    fn name(&self) -> &str {
+        STATS_OVERLAY_NAME
+    }
+
+    fn on_engine_event(&mut self, _ctx: &EngineContext, event: &EngineEvent) {
+        if let EngineEvent::ToggleDebugOverlay(name) = event {
+            if name == STATS_OVERLAY_NAME {
+                self.visible = !self.visible;
+            }
+        }
+    }
+
+    fn render(&mut self, ctx: &EngineContext, commands: &mut CommandBuffer) {
+        if !self.visible {
+            return;
+        }
+
+        let stats = ctx.profiler.stats();
+        let last_frame = ctx.profiler.last().map(|timings| timings.frame).unwrap_or_default();
+        let lines = [
+            format!("fps {:.1} frame {:.2}ms worst {:.2}ms last {:.2}ms",
+                stats.frames_per_second,
+                milliseconds(stats.average_frame),
+                milliseconds(stats.worst_frame),
+                milliseconds(last_frame)),
+            format!("update {:.2}ms fixed {:.2}ms x{:.2}",
+                milliseconds(stats.average_update),
+                milliseconds(stats.average_update_fixed),
+                stats.average_fixed_steps),
+            format!("render {:.2}ms dropped {}",
+                milliseconds(stats.average_render),
+                stats.dropped_frames),
+            format!("time {:.1}s tick {} rate {:.2}ms scale x{:.2}{}",
+                ctx.time.runtime.as_secs_f64(),
+                ctx.time.tick,
+                milliseconds(ctx.time.simulation_rate),
+                ctx.time.time_scale,
+                if ctx.time.paused { " paused" } else { "" })
+        ];
+
+        let mut creator = commands.add_commands();
+        for (y, line) in lines.iter().enumerate() {
+            for (x, character) in line.chars().enumerate() {
+                creator = creator.execute(Command::Draw(DrawInfo {
+                    colour: Colour::yellow(),
+                    draw_pos_x: x as u16,
+                    draw_pos_y: y as u16,
+                    character,
+                    ..DrawInfo::default()
+                }));
+            }
+        }
+        creator.compile();
+    }
+}
@@ -0,0 +1,62 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crossterm::{ cursor, event, execute, terminal };
+use std::io::{ self, stdout };
+use std::sync::atomic::{ AtomicBool, Ordering };
+
+/// If the terminal is currently set up for the game
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Put the terminal back into the state we found it in. Safe to call more than once
+pub fn restore() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        let _ = execute!(
+            stdout(),
+            event::DisableMouseCapture,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Sets the terminal up for the game: raw mode, the alternate screen, a hidden cursor
+/// and mouse capture. The terminal is restored when the guard is dropped. Panics are
+/// handled by the crash reporter's hook, which restores it before printing anything
+pub struct TerminalGuard {}
+
+impl TerminalGuard {
+    pub fn enter() -> io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        ACTIVE.store(true, Ordering::SeqCst);
+        execute!(
+            stdout(),
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            event::EnableMouseCapture
+        ).inspect_err(|_| restore())?;
+        Ok(TerminalGuard {})
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
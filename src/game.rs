@@ -17,4 +17,6 @@
 */
 
 pub mod game;
+pub mod pause_menu;
+pub mod console;
 
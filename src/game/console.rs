@@ -0,0 +1,169 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::engine_temp::game::state::{ State, InputResult };
+use crate::engine_temp::game::context::Context;
+use crate::engine_temp::input::{ Input, Key };
+use crate::engine_temp::input::action::{ Action, InputContext };
+use crate::engine_temp::logging::{ LogBuffer, LogFilter };
+use crate::renderer::crossterm::command::{ Command, DrawInfo };
+use crate::renderer::crossterm::command_buffer::CommandBuffer;
+use crate::renderer::colours::Colour;
+use crate::renderer::palette::Palette;
+use crossterm::style::{ Attribute, Attributes };
+use log::{ Level, LevelFilter };
+
+const CONSOLE_WIDTH: usize = 80;
+/// Lines of log shown beneath the header
+const CONSOLE_LINES: usize = 10;
+const LEVELS: [LevelFilter; 5] = [
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace
+];
+
+fn level_colour(palette: &Palette, level: Level) -> Colour {
+    match level {
+        Level::Error => palette.get_or("log_error", Colour::red()),
+        Level::Warn => palette.get_or("log_warn", Colour::yellow()),
+        Level::Info => palette.get_or("log_info", Colour::white()),
+        Level::Debug => palette.get_or("log_debug", Colour::gray()),
+        Level::Trace => palette.get_or("log_trace", Colour::dark_gray())
+    }
+}
+
+/// Shows the most recent log messages over the top of the screen. Typing filters by
+/// module, left and right change the lowest level shown and up and down scroll
+pub struct Console {
+    log_buffer: LogBuffer,
+    filter: LogFilter,
+    /// How many lines up from the newest message we have scrolled
+    scroll: usize
+}
+
+impl Console {
+    pub fn new(log_buffer: LogBuffer) -> Console {
+        Console {
+            log_buffer,
+            filter: LogFilter {
+                level: LevelFilter::Info,
+                module: String::new()
+            },
+            scroll: 0
+        }
+    }
+
+    fn change_level(&mut self, by: isize) {
+        let index = LEVELS.iter().position(|level| *level == self.filter.level).unwrap_or(0);
+        let index = (index as isize + by).clamp(0, LEVELS.len() as isize - 1);
+        self.filter.level = LEVELS[index as usize];
+        self.scroll = 0;
+    }
+
+    fn draw_line(draws: &mut Vec<Command>, y: usize, text: &str, colour: Colour, attributes: Attributes) {
+        // Pad out to the full width so nothing beneath the console shows through
+        let padded = text.chars().chain(std::iter::repeat(' ')).take(CONSOLE_WIDTH);
+        for (x, character) in padded.enumerate() {
+            draws.push(Command::Draw(DrawInfo {
+                colour,
+                background: Colour::black(),
+                attributes,
+                draw_pos_x: x as u16,
+                draw_pos_y: y as u16,
+                character
+            }));
+        }
+    }
+}
+
+impl State for Console {
+    fn render(&mut self, ctx: &mut Context, commands: &mut CommandBuffer, _alpha: f64) {
+        let palette = ctx.engine.palette;
+        let records = self.log_buffer.records(&self.filter);
+        self.scroll = self.scroll.min(records.len().saturating_sub(CONSOLE_LINES));
+        let end = records.len() - self.scroll;
+        let start = end.saturating_sub(CONSOLE_LINES);
+
+        let header = format!("console | level {} | module \"{}\" | {} messages",
+            self.filter.level, self.filter.module, records.len());
+        let mut draws = Vec::new();
+        Console::draw_line(&mut draws, 0, &header, palette.get_or("console_header", Colour::cyan()), Attribute::Reverse.into());
+        for line in 0..CONSOLE_LINES {
+            match records[start..end].get(line) {
+                Some(record) => {
+                    let text = format!("{:<5} {}: {}", record.level, record.module, record.message);
+                    Console::draw_line(&mut draws, line + 1, &text, level_colour(palette, record.level), Attributes::default());
+                },
+                None => Console::draw_line(&mut draws, line + 1, "", palette.get_or("log_info", Colour::white()), Attributes::default())
+            }
+        }
+
+        let mut creator = commands.add_commands();
+        for draw in draws {
+            creator = creator.execute(draw);
+        }
+        creator.compile();
+    }
+
+    fn updates_below(&self) -> bool {
+        true
+    }
+
+    fn renders_below(&self) -> bool {
+        true
+    }
+
+    fn input_context(&self) -> InputContext {
+        InputContext::Menu
+    }
+
+    fn on_input(&mut self, ctx: &mut Context, input: &Input, action: Option<Action>) -> InputResult {
+        if let Some(Action::ToggleConsole) | Some(Action::Cancel) = action {
+            ctx.states.queue_pop();
+            return InputResult::Consumed;
+        }
+
+        if let Input::Key(key_input) = input {
+            let modifiers = &key_input.modifiers;
+            match key_input.key {
+                Key::Char(character) if !modifiers.control && !modifiers.alt => {
+                    self.filter.module.push(character);
+                    self.scroll = 0;
+                    return InputResult::Consumed;
+                },
+                Key::Backspace => {
+                    self.filter.module.pop();
+                    self.scroll = 0;
+                    return InputResult::Consumed;
+                },
+                _ => {}
+            }
+        }
+
+        match action {
+            Some(Action::MenuUp) => self.scroll += 1,
+            Some(Action::MenuDown) => self.scroll = self.scroll.saturating_sub(1),
+            Some(Action::MenuLeft) => self.change_level(-1),
+            Some(Action::MenuRight) => self.change_level(1),
+            _ => return InputResult::Ignored
+        }
+        InputResult::Consumed
+    }
+}
@@ -15,32 +15,78 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
-use crate::engine_temp::game::state::State;
+use crate::engine_temp::game::state::{ State, StateData, InputResult };
+use crate::engine_temp::game::context::Context;
+use crate::engine_temp::engine::EngineEvent;
+use crate::engine_temp::stats_overlay::STATS_OVERLAY_NAME;
+use crate::engine_temp::input::Input;
+use crate::engine_temp::input::action::Action;
 use crate::engine_temp::ecs::world::{ World, WorldComponents };
 use crate::engine_temp::ecs::entity::Entity;
+use crate::engine_temp::logging::LogBuffer;
+use crate::game::pause_menu::{ PauseMenu, PauseMenuChoice };
+use crate::game::console::Console;
+use std::path::PathBuf;
 
 pub struct Game {
-    world: World
+    world: World,
+    save_path: PathBuf,
+    log_buffer: LogBuffer
 }
 
 impl Game {
-    pub fn new() -> Game {
+    pub fn new<P>(save_path: P, log_buffer: LogBuffer) -> Game where
+        P: Into<PathBuf> {
         let mut world = World::new();
         world.create_prefab("test", Box::new(|world: &mut WorldComponents, entity: &Entity| {
             world.transforms.create(entity);
         }));
 
         Game{
-            world
+            world,
+            save_path: save_path.into(),
+            log_buffer
         }
     }
 }
 
 impl State for Game {
-    fn on_push(&mut self) {
-        println!("push!");
+    fn on_push(&mut self, _ctx: &mut Context, _data: Option<StateData>) {
         self.world.create_entity_from_prefab("test");
-        println!("{:?}", self.world);
+        log::debug!("{:?}", self.world);
+    }
+
+    fn on_resume(&mut self, ctx: &mut Context, result: Option<StateData>) {
+        let choice = result.and_then(|result| result.downcast::<PauseMenuChoice>().ok());
+        match choice.map(|choice| *choice) {
+            Some(PauseMenuChoice::Save) => ctx.engine.events.send(EngineEvent::RequestSave),
+            Some(PauseMenuChoice::Quit) => ctx.engine.events.send(EngineEvent::Stop),
+            _ => {}
+        }
+    }
+
+    fn on_input(&mut self, ctx: &mut Context, _input: &Input, action: Option<Action>) -> InputResult {
+        match action {
+            Some(Action::OpenMenu) => ctx.states.queue_push(Box::new(PauseMenu::new())),
+            Some(Action::Quit) => ctx.engine.events.send(EngineEvent::Stop),
+            Some(Action::ToggleConsole) => ctx.states.queue_push(Box::new(Console::new(self.log_buffer.clone()))),
+            Some(Action::ToggleStatsOverlay) => {
+                ctx.engine.events.send(EngineEvent::ToggleDebugOverlay(STATS_OVERLAY_NAME.to_string()))
+            },
+            _ => return InputResult::Ignored
+        }
+        InputResult::Consumed
+    }
+
+    fn crash_data(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(&self.world).ok()
+    }
+
+    fn on_save(&mut self, _ctx: &mut Context) {
+        match self.world.save(&self.save_path) {
+            Ok(()) => log::info!("Saved world to {}", self.save_path.display()),
+            Err(e) => log::error!("Could not save world to {}: {}", self.save_path.display(), e)
+        }
     }
 }
 
@@ -0,0 +1,114 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::engine_temp::game::state::{ State, StateData, InputResult };
+use crate::engine_temp::game::context::Context;
+use crate::engine_temp::engine::EngineEvent;
+use crate::engine_temp::input::Input;
+use crate::engine_temp::input::action::{ Action, InputContext };
+use crate::renderer::crossterm::command::{ Command, DrawInfo };
+use crate::renderer::crossterm::command_buffer::CommandBuffer;
+use crate::renderer::colours::Colour;
+
+const PAUSED_TEXT: &str = "Paused";
+
+/// What the player chose from the pause menu. Given to the state beneath when the menu
+/// is closed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PauseMenuChoice {
+    Resume,
+    Save,
+    Quit
+}
+
+const CHOICES: [(PauseMenuChoice, &str); 3] = [
+    (PauseMenuChoice::Resume, "Resume"),
+    (PauseMenuChoice::Save, "Save"),
+    (PauseMenuChoice::Quit, "Quit")
+];
+
+/// Pauses the simulation while open
+pub struct PauseMenu {
+    selected: usize
+}
+
+impl PauseMenu {
+    pub fn new() -> PauseMenu {
+        PauseMenu {
+            selected: 0
+        }
+    }
+
+    fn close(ctx: &mut Context, choice: PauseMenuChoice) {
+        ctx.states.queue_pop_with(Box::new(choice));
+    }
+}
+
+impl State for PauseMenu {
+    fn on_push(&mut self, ctx: &mut Context, _data: Option<StateData>) {
+        ctx.engine.events.send(EngineEvent::Pause);
+    }
+
+    fn on_pop(&mut self, ctx: &mut Context) {
+        ctx.engine.events.send(EngineEvent::Resume);
+    }
+
+    fn render(&mut self, ctx: &mut Context, commands: &mut CommandBuffer, _alpha: f64) {
+        let colour = ctx.engine.palette.get_or("menu_text", Colour::white());
+        let selected_colour = ctx.engine.palette.get_or("menu_selected", Colour::yellow());
+
+        let lines = std::iter::once((PAUSED_TEXT.to_string(), colour))
+            .chain(CHOICES.iter().enumerate().map(|(index, (_, name))| match index == self.selected {
+                true => (format!("> {}", name), selected_colour),
+                false => (format!("  {}", name), colour)
+            }));
+
+        let mut creator = commands.add_commands();
+        for (y, (line, colour)) in lines.enumerate() {
+            for (x, character) in line.chars().enumerate() {
+                creator = creator.execute(Command::Draw(DrawInfo {
+                    colour,
+                    draw_pos_x: x as u16,
+                    draw_pos_y: y as u16,
+                    character,
+                    ..DrawInfo::default()
+                }));
+            }
+        }
+        creator.compile();
+    }
+
+    fn renders_below(&self) -> bool {
+        true
+    }
+
+    fn input_context(&self) -> InputContext {
+        InputContext::Menu
+    }
+
+    fn on_input(&mut self, ctx: &mut Context, _input: &Input, action: Option<Action>) -> InputResult {
+        match action {
+            Some(Action::MenuUp) => self.selected = self.selected.checked_sub(1).unwrap_or(CHOICES.len() - 1),
+            Some(Action::MenuDown) => self.selected = (self.selected + 1) % CHOICES.len(),
+            Some(Action::Confirm) => PauseMenu::close(ctx, CHOICES[self.selected].0),
+            Some(Action::Cancel) => PauseMenu::close(ctx, PauseMenuChoice::Resume),
+            _ => return InputResult::Ignored
+        }
+        InputResult::Consumed
+    }
+}
@@ -19,29 +19,175 @@
 mod engine_temp;
 mod renderer;
 mod game;
+mod cli;
+mod settings;
 
+use crate::cli::{ Command, Options };
+use crate::settings::{ Settings, SettingsWriter };
+use crate::engine_temp::crash::{ self, PanicDetails };
 use crate::engine_temp::engine::Engine;
-use crate::engine_temp::fence::FenceRC;
+use crate::engine_temp::input::poller::InputPoller;
+use crate::engine_temp::input::keymap::Keymap;
+use crate::engine_temp::logging::{ BufferLogger, LogBuffer, RotatingFile };
+use crate::engine_temp::replay::{ Replay, ReplayRecorder };
+use crate::engine_temp::stats_overlay::StatsOverlay;
+use crate::engine_temp::terminal::TerminalGuard;
+
+use crate::renderer::crossterm::render_engine::RenderEngine;
 
 use game::game::Game;
 
 use std::sync::mpsc;
+use std::path::Path;
+use std::process::ExitCode;
+use std::panic::{ self, AssertUnwindSafe };
 
 use simplelog::*;
 
-fn main() {
-    CombinedLogger::init(
-        vec![
-            TermLogger::new(LevelFilter::Debug, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
-        ]
-    ).unwrap();
+const KEYMAP_PATH: &str = "keymap.json";
+const CRASH_DIRECTORY: &str = "crashes";
+
+const LOG_FILE_MAX_BYTES: u64 = 1024 * 1024;
+const LOG_FILES_KEPT: usize = 4;
+const LOG_BUFFER_CAPACITY: usize = 512;
+
+/// Log to a rotating file and to a buffer the in-game console reads from. The terminal is
+/// only logged to when headless, since it would draw over the renderer otherwise
+fn init_logging(options: &Options, log_buffer: &LogBuffer) -> Result<(), String> {
+    let file = RotatingFile::open(&options.log_file, LOG_FILE_MAX_BYTES, LOG_FILES_KEPT)
+        .map_err(|e| format!("could not open log file {}: {}", options.log_file.display(), e))?;
+
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![
+        WriteLogger::new(options.log_level, Config::default(), file),
+        BufferLogger::new(options.log_level, log_buffer.clone())
+    ];
+    if options.headless {
+        loggers.push(TermLogger::new(options.log_level, Config::default(), TerminalMode::Stderr, ColorChoice::Auto));
+    }
+    CombinedLogger::init(loggers).map_err(|e| format!("could not set up logging: {}", e))
+}
 
-    loop {
+fn main() -> ExitCode {
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return ExitCode::SUCCESS;
+        },
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
 
+    let log_buffer = LogBuffer::new(LOG_BUFFER_CAPACITY);
+    if let Err(e) = init_logging(&options, &log_buffer) {
+        eprintln!("error: {}", e);
+        return ExitCode::FAILURE;
     }
 
-    let (_send, recv) = mpsc::channel();
-    let mut engine = Engine::new(recv, None);
-    engine.game_handler.state_machine.queue_push(Box::new(Game::new()));
-    engine.run();
+    crash::install_panic_hook();
+
+    let file_settings = match Settings::load_or_default(&options.settings_path) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("error: could not load settings from {}: {}", options.settings_path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut settings = file_settings.clone();
+    settings.merge_options(&options);
+    // Only the running game's copy is resolved, so an automatic mode is still written back
+    // to the file as automatic
+    settings.display.colour_mode = settings.display.colour_mode.resolve();
+
+    let palette = match settings.load_palette() {
+        Ok(palette) => palette,
+        Err(e) => {
+            eprintln!("error: could not load palette: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let replay = match &options.replay {
+        Some(path) => match Replay::load(path) {
+            Ok(replay) => Some(replay),
+            Err(e) => {
+                eprintln!("error: could not load replay {}: {}", path.display(), e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None
+    };
+
+    // Restores the terminal when dropped, after everything below has shut down
+    let _terminal = match options.headless {
+        true => None,
+        false => Some(TerminalGuard::enter().expect("Could not set up the terminal"))
+    };
+
+    let render_engine = match options.headless {
+        true => None,
+        false => {
+            let mut render_engine = RenderEngine::new(settings.device_settings());
+            render_engine.init();
+            Some(render_engine)
+        }
+    };
+    let frame_pipeline = render_engine.as_ref().map(|render_engine| render_engine.frame_pipeline());
+
+    let (send, recv) = mpsc::channel();
+    let (mut engine, mut input_poller) = match replay {
+        Some(replay) => (Engine::from_replay(replay, frame_pipeline), None),
+        None => (Engine::new(recv, frame_pipeline), Some(InputPoller::new(send)))
+    };
+    engine = engine
+        .with_plugin(Box::new(StatsOverlay::new()))
+        .with_plugin(Box::new(SettingsWriter::new(&options.settings_path, file_settings)));
+    if let Some(record) = &options.record {
+        engine = engine.with_plugin(Box::new(ReplayRecorder::new(record)));
+    }
+    settings.apply_simulation(&mut engine.game_handler);
+    engine.set_stop_after_replay(options.headless);
+
+    if let Some(seed) = options.seed {
+        engine.set_seed(seed);
+    }
+    log::info!("Starting with seed {}", engine.seed());
+
+    if Path::new(KEYMAP_PATH).exists() {
+        match Keymap::load(KEYMAP_PATH) {
+            Ok(keymap) => engine.set_keymap(keymap),
+            Err(e) => log::error!("Could not load keymap from {}: {}", KEYMAP_PATH, e)
+        }
+    }
+
+    engine.set_palette(palette);
+
+    if let Some(render_engine) = render_engine {
+        engine.set_render_engine(render_engine);
+    }
+
+    engine.game_handler.state_machine.queue_push(Box::new(Game::new(options.save_path, log_buffer)));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| engine.run()));
+    let exit_code = match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(payload) => {
+            let report = engine.crashed(PanicDetails::take(payload.as_ref()));
+            match report.save(CRASH_DIRECTORY) {
+                Ok(path) => {
+                    log::error!("Crashed, wrote a crash report to {}", path.display());
+                    eprintln!("The game crashed. Please send us the report written to {}", path.display());
+                },
+                Err(e) => log::error!("Crashed, and could not write a crash report: {}", e)
+            }
+            ExitCode::FAILURE
+        }
+    };
+
+    drop(engine);
+    if let Some(input_poller) = &mut input_poller {
+        input_poller.stop();
+    }
+    exit_code
 }
@@ -18,3 +18,4 @@
 
 pub mod colours;
 pub mod crossterm;
+pub mod palette;
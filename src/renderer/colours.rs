@@ -15,7 +15,23 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
+use serde::{ Serialize, Deserialize };
+use thiserror::Error;
+use std::fmt;
+use std::str::FromStr;
 
+#[derive(Error, Debug, PartialEq)]
+pub enum ColourError {
+    #[error("\"{0}\" is not a hex colour, expected #RGB, #RGBA, #RRGGBB or #RRGGBBAA")]
+    HexLength(String),
+    #[error("\"{0}\" has a character that is not a hex digit")]
+    HexDigit(String)
+}
+
+/// A colour as 8 bit RGB with alpha. In JSON a colour is either a hex string or an object
+/// of its channels, where alpha is optional
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "ColourRepr")]
 pub struct Colour {
     pub r: u8,
     pub g: u8,
@@ -23,6 +39,56 @@ pub struct Colour {
     pub a: u8
 }
 
+fn opaque() -> u8 {
+    0xFF
+}
+
+/// The forms a colour can be read from
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColourRepr {
+    Hex(String),
+    Channels {
+        r: u8,
+        g: u8,
+        b: u8,
+        #[serde(default = "opaque")]
+        a: u8
+    }
+}
+
+impl TryFrom<ColourRepr> for Colour {
+    type Error = ColourError;
+
+    fn try_from(repr: ColourRepr) -> Result<Colour, ColourError> {
+        match repr {
+            ColourRepr::Hex(hex) => Colour::hex(&hex),
+            ColourRepr::Channels { r, g, b, a } => Ok(Colour { r, g, b, a })
+        }
+    }
+}
+
+/// Hue in degrees, with saturation and value between 0 and 1
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Hsv {
+    pub hue: f64,
+    pub saturation: f64,
+    pub value: f64
+}
+
+/// Hue in degrees, with saturation and lightness between 0 and 1
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Hsl {
+    pub hue: f64,
+    pub saturation: f64,
+    pub lightness: f64
+}
+
+/// Turn a channel between 0 and 1 back into 8 bits
+fn to_channel(value: f64) -> u8 {
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
 impl Colour {
     pub fn rgb(r: u8, g: u8, b: u8) -> Colour {
         Colour { r, g, b, a: 0xFF }
@@ -32,46 +98,154 @@ impl Colour {
         Colour { r, g, b, a }
     }
 
-    pub fn hex(hex: &str) -> Colour {
-        if hex.len() < 6 {
-            return Colour::black()
+    /// Parse `#RGB`, `#RGBA`, `#RRGGBB` or `#RRGGBBAA`. The `#` is optional
+    pub fn hex(hex: &str) -> Result<Colour, ColourError> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ColourError::HexDigit(hex.to_string()));
         }
 
-        if hex.chars().find(|c| -> bool { c.is_ascii_hexdigit() }).is_some() {
-            return Colour::black()
+        // Every character is an ASCII hex digit, so each is a single byte
+        let digit = |index: usize| u8::from_str_radix(&digits[index..index + 1], 16).unwrap();
+        let pair = |index: usize| u8::from_str_radix(&digits[index..index + 2], 16).unwrap();
+        match digits.len() {
+            // A short digit is repeated, so #f80 is #ff8800
+            3 | 4 => {
+                let channels: Vec<u8> = (0..digits.len()).map(|index| digit(index) * 0x11).collect();
+                Ok(Colour::rgba(channels[0], channels[1], channels[2], channels.get(3).copied().unwrap_or(0xFF)))
+            },
+            6 => Ok(Colour::rgb(pair(0), pair(2), pair(4))),
+            8 => Ok(Colour::rgba(pair(0), pair(2), pair(4), pair(6))),
+            _ => Err(ColourError::HexLength(hex.to_string()))
         }
+    }
 
-        let r_slice = &hex[0..1];
-        let g_slice = &hex[2..3];
-        let b_slice = &hex[4..5];
+    pub fn with_alpha(&self, a: u8) -> Colour {
+        Colour { a, ..*self }
+    }
 
-        Colour {
-            r: u8::from_str_radix(r_slice, 16).unwrap() as u8,
-            g: u8::from_str_radix(g_slice, 16).unwrap() as u8,
-            b: u8::from_str_radix(b_slice, 16).unwrap() as u8,
-            a: 0xFF
-        }
+    fn from_hue_chroma(hue: f64, chroma: f64, offset: f64) -> Colour {
+        let sector = hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+        let (r, g, b) = match sector as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x)
+        };
+        Colour::rgb(to_channel(r + offset), to_channel(g + offset), to_channel(b + offset))
+    }
+
+    /// The hue, the largest channel and the smallest channel, all but the hue between 0 and 1
+    fn hue_max_min(self) -> (f64, f64, f64) {
+        let (r, g, b) = (self.r as f64 / 255.0, self.g as f64 / 255.0, self.b as f64 / 255.0);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        (hue.rem_euclid(360.0), max, min)
+    }
+
+    /// An opaque colour from its hue, saturation and value
+    pub fn from_hsv(hsv: Hsv) -> Colour {
+        let saturation = hsv.saturation.clamp(0.0, 1.0);
+        let value = hsv.value.clamp(0.0, 1.0);
+        let chroma = value * saturation;
+        Colour::from_hue_chroma(hsv.hue, chroma, value - chroma)
     }
 
-    pub fn hex_alpha(hex: &str) -> Colour {
-        if hex.len() < 8 {
-            return Colour::black()
+    pub fn to_hsv(self) -> Hsv {
+        let (hue, max, min) = self.hue_max_min();
+        Hsv {
+            hue,
+            saturation: if max == 0.0 { 0.0 } else { (max - min) / max },
+            value: max
         }
+    }
 
-        if hex.chars().find(|c| -> bool { c.is_ascii_hexdigit() }).is_some() {
-            return Colour::black()
+    /// An opaque colour from its hue, saturation and lightness
+    pub fn from_hsl(hsl: Hsl) -> Colour {
+        let saturation = hsl.saturation.clamp(0.0, 1.0);
+        let lightness = hsl.lightness.clamp(0.0, 1.0);
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        Colour::from_hue_chroma(hsl.hue, chroma, lightness - chroma / 2.0)
+    }
+
+    pub fn to_hsl(self) -> Hsl {
+        let (hue, max, min) = self.hue_max_min();
+        let lightness = (max + min) / 2.0;
+        let saturation = if max == min {
+            0.0
+        } else {
+            (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+        };
+        Hsl { hue, saturation, lightness }
+    }
+
+    /// Change the colour in HSL space, keeping its alpha
+    fn adjust_hsl<F>(&self, adjust: F) -> Colour where
+        F: FnOnce(&mut Hsl) {
+        let mut hsl = self.to_hsl();
+        adjust(&mut hsl);
+        Colour::from_hsl(hsl).with_alpha(self.a)
+    }
+
+    /// Raise the lightness by an amount between 0 and 1
+    pub fn lighten(&self, amount: f64) -> Colour {
+        self.adjust_hsl(|hsl| hsl.lightness += amount)
+    }
+
+    /// Lower the lightness by an amount between 0 and 1
+    pub fn darken(&self, amount: f64) -> Colour {
+        self.lighten(-amount)
+    }
+
+    /// Raise the saturation by an amount between 0 and 1
+    pub fn saturate(&self, amount: f64) -> Colour {
+        self.adjust_hsl(|hsl| hsl.saturation += amount)
+    }
+
+    /// Lower the saturation by an amount between 0 and 1
+    pub fn desaturate(&self, amount: f64) -> Colour {
+        self.saturate(-amount)
+    }
+
+    /// Linearly interpolate every channel, alpha included. `t` is clamped between 0 and 1
+    pub fn lerp(&self, other: &Colour, t: f64) -> Colour {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let channel = |from: u8, to: u8| (from as f64 + (to as f64 - from as f64) * t).round() as u8;
+        Colour {
+            r: channel(self.r, other.r),
+            g: channel(self.g, other.g),
+            b: channel(self.b, other.b),
+            a: channel(self.a, other.a)
         }
+    }
 
-        let r_slice = &hex[0..1];
-        let g_slice = &hex[2..3];
-        let b_slice = &hex[4..5];
-        let a_slice = &hex[6..7];
+    /// Composite this colour over an opaque colour using this colour's alpha. The result
+    /// is opaque
+    pub fn blend_over(&self, below: &Colour) -> Colour {
+        let alpha = self.a as u32;
+        let channel = |above: u8, below: u8| -> u8 {
+            ((above as u32 * alpha + below as u32 * (0xFF - alpha) + 0x7F) / 0xFF) as u8
+        };
 
         Colour {
-            r: u8::from_str_radix(r_slice, 16).unwrap() as u8,
-            g: u8::from_str_radix(g_slice, 16).unwrap() as u8,
-            b: u8::from_str_radix(b_slice, 16).unwrap() as u8,
-            a: u8::from_str_radix(a_slice, 16).unwrap() as u8
+            r: channel(self.r, below.r),
+            g: channel(self.g, below.g),
+            b: channel(self.b, below.b),
+            a: 0xFF
         }
     }
 
@@ -93,3 +267,188 @@ impl Colour {
     pub fn white() -> Colour        { Colour::rgb(0xee, 0xee, 0xec) }
 }
 
+impl FromStr for Colour {
+    type Err = ColourError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Colour::hex(s)
+    }
+}
+
+impl fmt::Display for Colour {
+    /// `#RRGGBB`, with the alpha appended if the colour isn't opaque
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)?;
+        if self.a != 0xFF {
+            write!(f, "{:02x}", self.a)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GradientStop {
+    /// Where the stop is along the gradient, usually between 0 and 1
+    pub position: f64,
+    pub colour: Colour
+}
+
+/// Colours that blend into each other along a line. In JSON a gradient is its list of stops
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<GradientStop>", into = "Vec<GradientStop>")]
+pub struct Gradient {
+    /// Always sorted by position
+    stops: Vec<GradientStop>
+}
+
+impl Gradient {
+    pub fn new(mut stops: Vec<GradientStop>) -> Gradient {
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+        Gradient { stops }
+    }
+
+    /// Space colours evenly from 0 to 1
+    pub fn even(colours: &[Colour]) -> Gradient {
+        let last = colours.len().saturating_sub(1).max(1) as f64;
+        Gradient::new(colours
+            .iter()
+            .enumerate()
+            .map(|(index, colour)| GradientStop { position: index as f64 / last, colour: *colour })
+            .collect())
+    }
+
+    pub fn stops(&self) -> &[GradientStop] {
+        &self.stops
+    }
+
+    /// The colour at a position. Before the first stop and after the last the gradient keeps
+    /// their colour. An empty gradient is transparent
+    pub fn at(&self, position: f64) -> Colour {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Colour::rgba(0, 0, 0, 0)
+        };
+
+        if position.is_nan() || position <= first.position {
+            return first.colour;
+        }
+        if position >= last.position {
+            return last.colour;
+        }
+
+        // The first stop past the position. There is always one before it since we are
+        // past the first stop
+        let next = self.stops.partition_point(|stop| stop.position <= position);
+        let (from, to) = (&self.stops[next - 1], &self.stops[next]);
+        let t = (position - from.position) / (to.position - from.position);
+        from.colour.lerp(&to.colour, t)
+    }
+}
+
+impl From<Vec<GradientStop>> for Gradient {
+    fn from(stops: Vec<GradientStop>) -> Gradient {
+        Gradient::new(stops)
+    }
+}
+
+impl From<Gradient> for Vec<GradientStop> {
+    fn from(gradient: Gradient) -> Vec<GradientStop> {
+        gradient.stops
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blend_over() {
+        let below = Colour::rgb(0x00, 0x80, 0xFF);
+        assert_eq!(Colour::rgba(0xFF, 0xFF, 0xFF, 0xFF).blend_over(&below), Colour::rgb(0xFF, 0xFF, 0xFF));
+        assert_eq!(Colour::rgba(0xFF, 0xFF, 0xFF, 0x00).blend_over(&below), below);
+        assert_eq!(Colour::rgba(0x00, 0x00, 0x00, 0x80).blend_over(&below), Colour::rgb(0x00, 0x40, 0x7F));
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(Colour::hex("#ff8800"), Ok(Colour::rgb(0xff, 0x88, 0x00)));
+        assert_eq!(Colour::hex("2E3436"), Ok(Colour::black()));
+        assert_eq!(Colour::hex("#f80"), Ok(Colour::rgb(0xff, 0x88, 0x00)));
+        assert_eq!(Colour::hex("#f808"), Ok(Colour::rgba(0xff, 0x88, 0x00, 0x88)));
+        assert_eq!(Colour::hex("#11223344"), Ok(Colour::rgba(0x11, 0x22, 0x33, 0x44)));
+        assert_eq!(Colour::hex("#12345"), Err(ColourError::HexLength("#12345".to_string())));
+        assert_eq!(Colour::hex("#12345g"), Err(ColourError::HexDigit("#12345g".to_string())));
+        assert_eq!(Colour::hex("#1é3"), Err(ColourError::HexDigit("#1é3".to_string())));
+
+        for colour in [Colour::red(), Colour::rgba(1, 2, 3, 4)] {
+            assert_eq!(colour.to_string().parse::<Colour>(), Ok(colour));
+        }
+    }
+
+    #[test]
+    fn test_hsv_and_hsl() {
+        let colour = Colour::rgb(0xff, 0x80, 0x00);
+        let hsv = colour.to_hsv();
+        assert!((hsv.hue - 30.1).abs() < 0.1);
+        assert_eq!(hsv.saturation, 1.0);
+        assert_eq!(hsv.value, 1.0);
+
+        let hsl = colour.to_hsl();
+        assert!((hsl.lightness - 0.5).abs() < 0.01);
+        assert_eq!(hsl.saturation, 1.0);
+
+        // every named colour survives a round trip
+        for colour in [Colour::black(), Colour::dark_purple(), Colour::gray(), Colour::cyan(), Colour::white()] {
+            assert_eq!(Colour::from_hsv(colour.to_hsv()), colour);
+            assert_eq!(Colour::from_hsl(colour.to_hsl()), colour);
+        }
+
+        assert_eq!(Colour::from_hsv(Hsv { hue: 240.0, saturation: 1.0, value: 1.0 }), Colour::rgb(0, 0, 0xff));
+        assert_eq!(Colour::from_hsl(Hsl { hue: -240.0, saturation: 1.0, lightness: 0.5 }), Colour::rgb(0, 0xff, 0));
+    }
+
+    #[test]
+    fn test_adjustments() {
+        let colour = Colour::rgba(0xcc, 0x33, 0x33, 0x80);
+        assert_eq!(colour.lighten(1.0), Colour::rgba(0xff, 0xff, 0xff, 0x80));
+        assert_eq!(colour.darken(1.0), Colour::rgba(0, 0, 0, 0x80));
+        assert!(colour.lighten(0.1).to_hsl().lightness > colour.to_hsl().lightness);
+
+        let grey = colour.desaturate(1.0);
+        assert_eq!((grey.r, grey.g, grey.b), (0x80, 0x80, 0x80));
+        assert!(colour.saturate(0.2).to_hsl().saturation > colour.to_hsl().saturation);
+    }
+
+    #[test]
+    fn test_gradient() {
+        let black = Colour::rgb(0, 0, 0);
+        let white = Colour::rgb(0xff, 0xff, 0xff);
+        assert_eq!(black.lerp(&white, 0.5), Colour::rgb(0x80, 0x80, 0x80));
+        assert_eq!(black.lerp(&white, 2.0), white);
+
+        let gradient = Gradient::even(&[black, Colour::rgb(0xff, 0, 0), white]);
+        assert_eq!(gradient.at(-1.0), black);
+        assert_eq!(gradient.at(0.25), Colour::rgb(0x80, 0, 0));
+        assert_eq!(gradient.at(0.5), Colour::rgb(0xff, 0, 0));
+        assert_eq!(gradient.at(0.75), Colour::rgb(0xff, 0x80, 0x80));
+        assert_eq!(gradient.at(1.5), white);
+        assert_eq!(Gradient::default().at(0.5).a, 0);
+
+        // stops are sorted however they are given
+        let json = r##"[{ "position": 1.0, "colour": "#fff" }, { "position": 0.0, "colour": "#000" }]"##;
+        let gradient: Gradient = serde_json::from_str(json).unwrap();
+        assert_eq!(gradient.at(0.5), Colour::rgb(0x80, 0x80, 0x80));
+    }
+
+    #[test]
+    fn test_serde() {
+        // colours are written as their channels, and read from either form
+        let colour = Colour::rgba(1, 2, 3, 4);
+        let json = serde_json::to_string(&colour).unwrap();
+        assert_eq!(json, r#"{"r":1,"g":2,"b":3,"a":4}"#);
+        assert_eq!(serde_json::from_str::<Colour>(&json).unwrap(), colour);
+        assert_eq!(serde_json::from_str::<Colour>(r#"{ "r": 1, "g": 2, "b": 3 }"#).unwrap(), Colour::rgb(1, 2, 3));
+        assert_eq!(serde_json::from_str::<Colour>(r##""#01020304""##).unwrap(), colour);
+        assert!(serde_json::from_str::<Colour>(r#""blue""#).is_err());
+    }
+}
@@ -16,10 +16,13 @@
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+pub mod cell_grid;
 mod colour;
-mod command;
-mod command_buffer;
-mod device_settings;
+pub mod command;
+pub mod command_buffer;
+pub mod device_settings;
 mod display_engine;
-mod render_engine;
+pub mod render_engine;
 mod swapchain;
+#[cfg(test)]
+pub mod virtual_terminal;
@@ -0,0 +1,460 @@
+/*
+    a roguelike game created for a fun exercise
+    copyright (c) 2023  bailey danyluk
+
+    this program is free software: you can redistribute it and/or modify
+    it under the terms of the gnu general public license as published by
+    the free software foundation, either version 3 of the license, or
+    (at your option) any later version.
+
+    this program is distributed in the hope that it will be useful,
+    but without any warranty; without even the implied warranty of
+    merchantability or fitness for a particular purpose.  see the
+    gnu general public license for more details.
+
+    you should have received a copy of the gnu general public license
+    along with this program.  if not, see <https://www.gnu.org/licenses/>.
+*/
+use std::io::{ self, Write };
+use crossterm::{ QueueableCommand, cursor };
+use crossterm::style::{ Attribute, Attributes, Color, Print, SetAttribute, SetAttributes, SetBackgroundColor, SetForegroundColor };
+use crossterm::terminal::{ Clear, ClearType };
+use crate::renderer::colours::Colour;
+use crate::renderer::crossterm::colour;
+use crate::renderer::crossterm::command::{ ClearInfo, DrawInfo };
+use crate::renderer::crossterm::device_settings::ColourMode;
+
+/// Unchanged cells between two changes on a row are printed again if there are at most
+/// this many of them, since that is cheaper than moving the cursor past them
+const MAX_BRIDGED_CELLS: u16 = 4;
+
+/// A single character on screen along with how it is drawn. Colours are kept as opaque
+/// RGB so draws can be blended over them, and only mapped to what the terminal can show
+/// when the frame is presented
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub character: char,
+    pub foreground: Colour,
+    pub background: Colour,
+    pub attributes: Attributes
+}
+
+impl Cell {
+    /// An empty cell on the given background
+    pub fn blank(background: Colour) -> Cell {
+        Cell {
+            character: ' ',
+            foreground: Colour::white(),
+            background,
+            attributes: Attributes::default()
+        }
+    }
+}
+
+/// The style the terminal is currently drawing with while a diff is written, so we only
+/// send what changes between cells
+struct Pen {
+    foreground: Color,
+    background: Color,
+    attributes: Attributes
+}
+
+impl Pen {
+    /// Change the terminal's style to draw a cell
+    fn apply<W>(&mut self, cell: &Cell, colour_mode: ColourMode, out: &mut W) -> io::Result<()> where
+        W: Write {
+        if cell.attributes != self.attributes {
+            // Attributes can only be added, so we start from nothing whenever one is removed.
+            // Resetting attributes also resets the colours
+            out.queue(SetAttribute(Attribute::Reset))?;
+            self.foreground = Color::Reset;
+            self.background = Color::Reset;
+            if !cell.attributes.is_empty() {
+                out.queue(SetAttributes(cell.attributes))?;
+            }
+            self.attributes = cell.attributes;
+        }
+
+        let foreground = colour::map_colour(&cell.foreground, colour_mode);
+        if foreground != self.foreground {
+            out.queue(SetForegroundColor(foreground))?;
+            self.foreground = foreground;
+        }
+
+        let background = colour::map_colour(&cell.background, colour_mode);
+        if background != self.background {
+            out.queue(SetBackgroundColor(background))?;
+            self.background = background;
+        }
+        Ok(())
+    }
+}
+
+/// A screen's worth of cells. Command buffers are drawn into a grid, which is then compared
+/// against the last grid shown so only the cells that changed are sent to the terminal
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellGrid {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+    /// What cleared cells are set to
+    blank: Cell,
+    /// Where the terminal's cursor would be after the commands drawn so far. Clears
+    /// relative to the cursor use it
+    cursor: (u16, u16)
+}
+
+impl CellGrid {
+    pub fn new(width: u16, height: u16, clear_colour: Colour) -> CellGrid {
+        let blank = Cell::blank(Colour { a: 0xFF, ..clear_colour });
+        CellGrid {
+            width,
+            height,
+            cells: vec![blank; width as usize * height as usize],
+            blank,
+            cursor: (0, 0)
+        }
+    }
+
+    /// Change the size of the grid. Everything on it is cleared
+    pub fn resize(&mut self, width: u16, height: u16) {
+        *self = CellGrid::new(width, height, self.blank.background);
+    }
+
+    fn index(&self, x: u16, y: u16) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    pub fn get(&self, x: u16, y: u16) -> Option<&Cell> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.cells.get(self.index(x, y))
+    }
+
+    /// Set a cell. Anything off the grid is ignored. Like a terminal, the cursor is left
+    /// just past the cell
+    pub fn set(&mut self, x: u16, y: u16, cell: Cell) {
+        if x < self.width && y < self.height {
+            let index = self.index(x, y);
+            self.cells[index] = cell;
+        }
+        self.cursor = (x.saturating_add(1), y);
+    }
+
+    /// Blend a draw over the cell beneath it
+    pub fn draw(&mut self, draw_info: &DrawInfo) {
+        let (x, y) = (draw_info.draw_pos_x, draw_info.draw_pos_y);
+        let below = match self.get(x, y) {
+            Some(cell) => *cell,
+            None => self.blank
+        };
+
+        let background = draw_info.background.blend_over(&below.background);
+        let cell = if draw_info.colour.a == 0 {
+            // Nothing is drawn over the character, so the background tints it as well
+            Cell {
+                foreground: draw_info.background.blend_over(&below.foreground),
+                background,
+                ..below
+            }
+        } else {
+            Cell {
+                character: draw_info.character,
+                foreground: draw_info.colour.blend_over(&background),
+                background,
+                attributes: draw_info.attributes
+            }
+        };
+        self.set(x, y, cell);
+    }
+
+    pub fn move_cursor(&mut self, x: u16, y: u16) {
+        self.cursor = (x, y);
+    }
+
+    /// Clear the cells between two indices, clamped to the grid
+    fn clear_cells(&mut self, start: usize, end: usize) {
+        let end = end.min(self.cells.len());
+        if start < end {
+            self.cells[start..end].fill(self.blank);
+        }
+    }
+
+    /// Clear the part of the grid a clear command would clear on a terminal
+    pub fn clear(&mut self, clear_info: &ClearInfo) {
+        let width = self.width as usize;
+        let (x, y) = self.cursor;
+        // The cursor is left past the edge after drawing in the last column, but like a
+        // terminal a clear treats it as being on the last column
+        let column = |x: u16| x.min(self.width.saturating_sub(1));
+        let cursor = self.index(column(x), y);
+        let line_start = |row: u16| row as usize * width;
+
+        match *clear_info {
+            ClearInfo::All => self.clear_cells(0, self.cells.len()),
+            ClearInfo::AfterCursor => self.clear_cells(cursor, self.cells.len()),
+            ClearInfo::BeforeCursor => self.clear_cells(0, cursor + 1),
+            ClearInfo::CurrentLine => self.clear_cells(line_start(y), line_start(y) + width),
+            ClearInfo::UntilNewLine => self.clear_cells(cursor, line_start(y) + width),
+            ClearInfo::After(x, y) => self.clear_cells(self.index(column(x), y), self.cells.len()),
+            ClearInfo::Before(x, y) => self.clear_cells(0, self.index(column(x), y) + 1),
+            ClearInfo::Line(row) => self.clear_cells(line_start(row), line_start(row) + width),
+            ClearInfo::UntilNewLineFrom(x, y) => self.clear_cells(self.index(column(x), y), line_start(y) + width)
+        }
+    }
+
+    /// Write the commands that turn a terminal showing `previous` into one showing this grid,
+    /// with colours mapped to what the colour mode can show. Without a previous grid, or if
+    /// it is a different size, the whole screen is redrawn
+    pub fn diff<W>(&self, previous: Option<&CellGrid>, colour_mode: ColourMode, out: &mut W) -> io::Result<()> where
+        W: Write {
+        let previous = previous.filter(|previous| previous.width == self.width && previous.height == self.height);
+        let mut pen = Pen {
+            foreground: Color::Reset,
+            background: Color::Reset,
+            attributes: Attributes::default()
+        };
+
+        // We don't know what style the terminal was left in, so we start from a reset.
+        // Terminals clear to the current background, so a full redraw starts by filling
+        // the screen with blank cells
+        let mut pen_known = false;
+        if previous.is_none() {
+            out.queue(SetAttribute(Attribute::Reset))?;
+            pen.apply(&self.blank, colour_mode, out)?;
+            out.queue(Clear(ClearType::All))?;
+            pen_known = true;
+        }
+
+        let changed = |x: u16, y: u16| match previous {
+            Some(previous) => previous.get(x, y) != self.get(x, y),
+            None => self.get(x, y) != Some(&self.blank)
+        };
+
+        for y in 0..self.height {
+            let mut cursor: Option<u16> = None;
+            let mut x = 0;
+            while x < self.width {
+                if !changed(x, y) {
+                    x += 1;
+                    continue;
+                }
+
+                if !pen_known {
+                    out.queue(SetAttribute(Attribute::Reset))?;
+                    pen_known = true;
+                }
+
+                // Bridge short gaps since the cursor is already on this row
+                match cursor {
+                    Some(cursor_x) if cursor_x <= x && x - cursor_x <= MAX_BRIDGED_CELLS => {
+                        for bridged in cursor_x..x {
+                            let cell = &self.cells[self.index(bridged, y)];
+                            pen.apply(cell, colour_mode, out)?;
+                            out.queue(Print(cell.character))?;
+                        }
+                    },
+                    _ => {
+                        out.queue(cursor::MoveTo(x, y))?;
+                    }
+                }
+
+                let cell = &self.cells[self.index(x, y)];
+                pen.apply(cell, colour_mode, out)?;
+                out.queue(Print(cell.character))?;
+                x += 1;
+                cursor = Some(x);
+            }
+        }
+
+        // Leave the terminal as we found it so nothing else draws in our style
+        if pen_known && (pen.foreground != Color::Reset || pen.background != Color::Reset || !pen.attributes.is_empty()) {
+            out.queue(SetAttribute(Attribute::Reset))?;
+        }
+        Ok(())
+    }
+
+    /// Check a terminal shows what is on this grid. The colour of a blank's foreground
+    /// can't be seen, so it isn't compared
+    #[cfg(test)]
+    pub fn assert_shown_on(&self, terminal: &crate::renderer::crossterm::virtual_terminal::VirtualTerminal, colour_mode: ColourMode) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = self.get(x, y).unwrap();
+                let shown = terminal.cell(x, y).unwrap();
+                assert_eq!(shown.character, cell.character, "character at {}, {}", x, y);
+                assert_eq!(shown.background, colour::map_colour(&cell.background, colour_mode), "background at {}, {}", x, y);
+                assert_eq!(shown.attributes, cell.attributes, "attributes at {}, {}", x, y);
+                if cell.character != ' ' {
+                    assert_eq!(shown.foreground, colour::map_colour(&cell.foreground, colour_mode), "foreground at {}, {}", x, y);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::crossterm::virtual_terminal::VirtualTerminal;
+
+    fn cell(character: char, foreground: Colour) -> Cell {
+        Cell { character, foreground, ..Cell::blank(Colour::black()) }
+    }
+
+    fn diff(grid: &CellGrid, previous: Option<&CellGrid>) -> Vec<u8> {
+        let mut out = Vec::new();
+        grid.diff(previous, ColourMode::Limited, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_unchanged() {
+        let mut grid = CellGrid::new(8, 4, Colour::black());
+        grid.set(3, 2, cell('@', Colour::yellow()));
+        assert!(diff(&grid, Some(&grid.clone())).is_empty());
+    }
+
+    #[test]
+    fn test_only_changes_are_sent() {
+        let mut previous = CellGrid::new(80, 24, Colour::black());
+        for y in 0..24 {
+            for x in 0..80 {
+                previous.set(x, y, cell('#', Colour::gray()));
+            }
+        }
+        let full = diff(&previous, None);
+
+        let mut grid = previous.clone();
+        grid.set(10, 5, cell('@', Colour::yellow()));
+        grid.set(12, 5, cell('g', Colour::green()));
+        let changes = diff(&grid, Some(&previous));
+
+        // one move, the bridged gap is printed instead of moving again
+        let mut expected = Vec::new();
+        expected
+            .queue(SetAttribute(Attribute::Reset)).unwrap()
+            .queue(cursor::MoveTo(10, 5)).unwrap()
+            .queue(SetForegroundColor(Color::Yellow)).unwrap()
+            .queue(SetBackgroundColor(Color::Black)).unwrap()
+            .queue(Print('@')).unwrap()
+            .queue(SetForegroundColor(Color::Grey)).unwrap()
+            .queue(Print('#')).unwrap()
+            .queue(SetForegroundColor(Color::Green)).unwrap()
+            .queue(Print('g')).unwrap()
+            .queue(SetAttribute(Attribute::Reset)).unwrap();
+        assert_eq!(changes, expected);
+        assert!(changes.len() * 20 < full.len());
+    }
+
+    #[test]
+    fn test_diff_matches_terminal() {
+        let mut terminal = VirtualTerminal::new(10, 3);
+        let mut previous = None;
+        let mut grid = CellGrid::new(10, 3, Colour::dark_blue());
+
+        let frames: [&[(u16, u16, char)]; 3] = [
+            &[(0, 0, 'a'), (9, 2, 'z')],
+            &[(1, 0, 'b'), (7, 0, 'c'), (9, 2, ' ')],
+            &[(0, 1, 'd'), (5, 1, 'e')]
+        ];
+        for frame in frames {
+            for (x, y, character) in frame {
+                grid.set(*x, *y, Cell {
+                    character: *character,
+                    foreground: Colour::rgb(*x as u8, 0, 0),
+                    background: Colour::blue(),
+                    attributes: Attribute::Bold.into()
+                });
+            }
+            grid.diff(previous.as_ref(), ColourMode::TrueColour, &mut terminal).unwrap();
+            grid.assert_shown_on(&terminal, ColourMode::TrueColour);
+            previous = Some(grid.clone());
+        }
+    }
+
+    #[test]
+    fn test_blending() {
+        let mut grid = CellGrid::new(2, 1, Colour::rgb(0x00, 0x00, 0x00));
+        grid.draw(&DrawInfo {
+            colour: Colour::rgb(0xFF, 0xFF, 0xFF),
+            background: Colour::rgb(0x00, 0x00, 0xFF),
+            character: '@',
+            ..DrawInfo::default()
+        });
+
+        // a translucent glyph fades into its background
+        grid.draw(&DrawInfo {
+            colour: Colour::rgba(0xFF, 0x00, 0x00, 0x80),
+            draw_pos_x: 1,
+            character: 'x',
+            ..DrawInfo::default()
+        });
+        assert_eq!(grid.get(1, 0), Some(&Cell { character: 'x', foreground: Colour::rgb(0x80, 0x00, 0x00), ..grid.blank }));
+
+        // a transparent foreground tints what is already there
+        grid.draw(&DrawInfo {
+            colour: Colour::rgba(0, 0, 0, 0),
+            background: Colour::rgba(0x00, 0x00, 0x00, 0x80),
+            ..DrawInfo::default()
+        });
+        assert_eq!(grid.get(0, 0), Some(&Cell {
+            character: '@',
+            foreground: Colour::rgb(0x7F, 0x7F, 0x7F),
+            background: Colour::rgb(0x00, 0x00, 0x7F),
+            attributes: Attributes::default()
+        }));
+    }
+
+    #[test]
+    fn test_clears() {
+        let mut grid = CellGrid::new(4, 3, Colour::black());
+        for y in 0..3 {
+            for x in 0..4 {
+                grid.set(x, y, cell('x', Colour::white()));
+            }
+        }
+
+        grid.move_cursor(1, 1);
+        grid.clear(&ClearInfo::UntilNewLine);
+        grid.clear(&ClearInfo::Before(0, 0));
+        grid.clear(&ClearInfo::Line(2));
+        let rows: Vec<String> = (0..3)
+            .map(|y| (0..4).map(|x| grid.get(x, y).unwrap().character).collect())
+            .collect();
+        assert_eq!(rows, vec![" xxx", "x   ", "    "]);
+    }
+
+    #[test]
+    fn test_clears_from_last_column() {
+        let mut grid = CellGrid::new(4, 2, Colour::black());
+        for y in 0..2 {
+            for x in 0..4 {
+                grid.set(x, y, cell('x', Colour::white()));
+            }
+        }
+        let mut terminal = VirtualTerminal::new(4, 2);
+        grid.diff(None, ColourMode::TrueColour, &mut terminal).unwrap();
+
+        // drawing in the last column leaves the cursor past the edge of the grid
+        grid.set(3, 0, cell('y', Colour::white()));
+        assert_eq!(grid.cursor, (4, 0));
+        grid.clear(&ClearInfo::BeforeCursor);
+        grid.move_cursor(4, 1);
+        grid.clear(&ClearInfo::UntilNewLine);
+
+        terminal
+            .queue(cursor::MoveTo(3, 0)).unwrap()
+            .queue(Print('y')).unwrap()
+            .queue(Clear(ClearType::FromCursorUp)).unwrap()
+            .queue(cursor::MoveTo(4, 1)).unwrap()
+            .queue(Clear(ClearType::UntilNewLine)).unwrap();
+
+        let rows: Vec<String> = (0..2)
+            .map(|y| (0..4).map(|x| grid.get(x, y).unwrap().character).collect())
+            .collect();
+        assert_eq!(rows, vec!["    ", "xxx "]);
+        assert_eq!(terminal.snapshot(), "\nxxx");
+    }
+}
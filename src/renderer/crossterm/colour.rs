@@ -17,6 +17,18 @@
 */
 use crossterm::style::Color;
 use crate::renderer::colours::Colour;
+use crate::renderer::crossterm::device_settings::ColourMode;
+
+/// Map from RGB8 to whatever the colour mode can display. An automatic colour mode should
+/// be resolved before drawing, until then it is treated as limited
+pub fn map_colour(original: &Colour, colour_mode: ColourMode) -> Color {
+    match colour_mode {
+        ColourMode::Monochrome => Color::Reset,
+        ColourMode::Auto | ColourMode::Limited => map_to_limited_colours(original),
+        ColourMode::Ansi256 => map_to_ansi_256(original),
+        ColourMode::TrueColour => map_to_crossterm_rgb(original)
+    }
+}
 
 /// Map from RGB8 to a limited colour set of 16 colours
 pub fn map_to_limited_colours(original: &Colour) -> Color {
@@ -68,3 +80,62 @@ pub fn map_to_crossterm_rgb(original: &Colour) -> Color {
     Color::Rgb { r: original.r, g: original.g, b: original.b }
 }
 
+/// How different two colours look. Weights each channel by how sensitive our eyes are to it
+/// depending on how red the colours are, which is much closer to what we see than the
+/// distance between RGB values
+/// https://www.compuphase.com/cmetric.htm
+fn perceptual_distance(a: &Colour, b: &Colour) -> u32 {
+    let red_mean = (a.r as i32 + b.r as i32) / 2;
+    let r = a.r as i32 - b.r as i32;
+    let g = a.g as i32 - b.g as i32;
+    let b = a.b as i32 - b.b as i32;
+    ((((512 + red_mean) * r * r) >> 8) + 4 * g * g + (((767 - red_mean) * b * b) >> 8)) as u32
+}
+
+/// The levels each channel of the xterm 6x6x6 colour cube can take
+const CUBE_LEVELS: [u8; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
+
+/// Map from RGB8 to the xterm 256 colour palette. The first 16 colours are left out since
+/// every terminal theme changes them, so only the colour cube and the grey ramp are used
+pub fn map_to_ansi_256(original: &Colour) -> Color {
+    let closest_level = |channel: u8| -> usize {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|index| (CUBE_LEVELS[*index] as i32 - channel as i32).unsigned_abs())
+            .unwrap()
+    };
+    let (r, g, b) = (closest_level(original.r), closest_level(original.g), closest_level(original.b));
+    let cube = Colour::rgb(CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]);
+    let cube_index = 16 + 36 * r + 6 * g + b;
+
+    // The grey ramp runs from 0x08 to 0xee in steps of 10
+    let average = (original.r as u32 + original.g as u32 + original.b as u32) / 3;
+    let grey_step = (average.saturating_sub(3) / 10).min(23);
+    let grey_level = (8 + grey_step * 10) as u8;
+    let grey = Colour::rgb(grey_level, grey_level, grey_level);
+    let grey_index = 232 + grey_step as usize;
+
+    if perceptual_distance(original, &grey) < perceptual_distance(original, &cube) {
+        Color::AnsiValue(grey_index as u8)
+    } else {
+        Color::AnsiValue(cube_index as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ansi_256() {
+        // exact palette colours map to themselves
+        assert_eq!(map_to_ansi_256(&Colour::rgb(0x00, 0x00, 0x00)), Color::AnsiValue(16));
+        assert_eq!(map_to_ansi_256(&Colour::rgb(0xff, 0x00, 0x00)), Color::AnsiValue(196));
+        assert_eq!(map_to_ansi_256(&Colour::rgb(0x5f, 0x87, 0xd7)), Color::AnsiValue(68));
+        assert_eq!(map_to_ansi_256(&Colour::rgb(0x80, 0x80, 0x80)), Color::AnsiValue(244));
+
+        // greys between cube levels use the grey ramp instead
+        assert_eq!(map_to_ansi_256(&Colour::rgb(0x30, 0x30, 0x30)), Color::AnsiValue(236));
+        assert_eq!(map_to_ansi_256(&Colour::rgb(0xfe, 0x01, 0x02)), Color::AnsiValue(196));
+        assert_eq!(map_colour(&Colour::red(), ColourMode::Monochrome), Color::Reset);
+    }
+}
@@ -16,18 +16,40 @@
     along with this program.  if not, see <https://www.gnu.org/licenses/>.
 */
 use crossterm::style;
+use crate::renderer::colours::Colour;
 
-#[derive(Clone, Copy, Debug)]
+/// Draw a character. Colours are blended over what is already on screen using their alpha,
+/// so a translucent background tints the cell beneath it. A fully transparent foreground
+/// keeps the character already there, letting a draw dim or highlight a cell without
+/// replacing it
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct DrawInfo {
-    pub colour: style::Color,
+    pub colour: Colour,
+    pub background: Colour,
+    /// Bold, dim, italic, underline, reverse and blink
+    pub attributes: style::Attributes,
     pub draw_pos_x: u16,
     pub draw_pos_y: u16,
     pub character: char
 }
 
+impl Default for DrawInfo {
+    /// A white blank at the origin with a transparent background
+    fn default() -> DrawInfo {
+        DrawInfo {
+            colour: Colour::white(),
+            background: Colour::rgba(0, 0, 0, 0),
+            attributes: style::Attributes::default(),
+            draw_pos_x: 0,
+            draw_pos_y: 0,
+            character: ' '
+        }
+    }
+}
+
 /// What kind of clearing we want to do on a line. Can define from current cursor, or from 
 /// a given position
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ClearInfo {
     /// Clear the entire visible screen. All draw commands before this are purged
     All,
@@ -55,8 +77,32 @@ pub enum ClearInfo {
     UntilNewLineFrom(u16, u16)
 }
 
+impl ClearInfo {
+    /// If what this clears depends on where the cursor is when it runs
+    pub fn is_relative_to_cursor(&self) -> bool {
+        matches!(self,
+            ClearInfo::AfterCursor |
+            ClearInfo::BeforeCursor |
+            ClearInfo::CurrentLine |
+            ClearInfo::UntilNewLine)
+    }
+
+    /// If this clear is known to erase the cell at a position before it runs. Clears
+    /// relative to the cursor never are
+    pub fn clears(&self, x: u16, y: u16) -> bool {
+        match *self {
+            ClearInfo::All => true,
+            ClearInfo::After(clear_x, clear_y) => y > clear_y || (y == clear_y && x >= clear_x),
+            ClearInfo::Before(clear_x, clear_y) => y < clear_y || (y == clear_y && x <= clear_x),
+            ClearInfo::Line(row) => y == row,
+            ClearInfo::UntilNewLineFrom(clear_x, clear_y) => y == clear_y && x >= clear_x,
+            _ => false
+        }
+    }
+}
+
 /// A single command to modify contents of the screen buffer
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Command {
     /// Clear the terminal
     Clear(ClearInfo),
@@ -15,53 +15,67 @@
     you should have received a copy of the gnu general public license
     along with this program.  if not, see <https://www.gnu.org/licenses/>.
 */
-use std::io::Write;
-use crossterm::{ QueueableCommand, cursor };
-use crossterm::terminal::{ Clear, ClearType };
-use crossterm::style::{ self, Print, SetForegroundColor };
+use crossterm::style;
+use crate::renderer::colours::Colour;
 use crate::renderer::crossterm::command::{ self, Command, ClearInfo };
+use crate::renderer::crossterm::cell_grid::CellGrid;
+
+/// Everything about how a character is drawn apart from the character itself
+#[derive(Clone, Copy, PartialEq)]
+struct Style {
+    foreground: Colour,
+    background: Colour,
+    attributes: style::Attributes
+}
+
+impl From<&command::DrawInfo> for Style {
+    fn from(draw_info: &command::DrawInfo) -> Style {
+        Style {
+            foreground: draw_info.colour,
+            background: draw_info.background,
+            attributes: draw_info.attributes
+        }
+    }
+}
 
-/// A meta-draw buffer which represents a consecutive set of draw buffers with colours
+/// A meta-draw buffer which represents a consecutive set of draw commands of one style
 #[derive(Clone)]
 struct ConsecutiveBuffer {
-    end: usize,
-    colour: style::Color,
+    style: Style,
     draw_commands: Vec<command::DrawInfo>
 }
 
 impl ConsecutiveBuffer {
-    fn new() -> ConsecutiveBuffer {
+    fn new(style: Style) -> ConsecutiveBuffer {
         ConsecutiveBuffer {
-            end: usize::MAX,
-            colour: style::Color::White,
+            style,
             draw_commands: Vec::new()
         }
     }
 
-    fn execute<'a, T>(&self, stdout: &'a mut T) -> &'a mut T where T: Write {
-        let mut stdout_queue = stdout;
-        stdout_queue = stdout_queue.queue(SetForegroundColor(self.colour)).unwrap();
+    fn rasterise(&self, grid: &mut CellGrid) {
         for draw_command in self.draw_commands.iter() {
-            stdout_queue = stdout_queue
-                .queue(cursor::MoveTo(draw_command.draw_pos_x, draw_command.draw_pos_y)).unwrap()
-                .queue(Print(draw_command.character)).unwrap()
+            grid.draw(draw_command);
         }
-        stdout_queue
     }
+}
 
-    fn prepare(&mut self, width: u16) {
-        // Sort by 1d position to ensure we are drawing consecutive characters
-        self.draw_commands.sort_by_key(|k| k.draw_pos_x + k.draw_pos_y * width);
-    }
+/// A compiled command within a draw buffer. Runs of draw commands are sorted and grouped
+/// by style, while anything else keeps its place between them
+#[derive(Clone)]
+enum CompiledCommand {
+    Draw(Vec<ConsecutiveBuffer>),
+    SetCursorPosition(u16, u16),
+    Flush
 }
 
-/// A draw buffer is a set of draw commands ended by a single clear command 
+/// A draw buffer is a set of draw commands started by a single clear command 
 /// We can order draw commands within a draw buffer to give us more control over when
 /// certain characters are drawn, such as ordering by x or y position, colour, etc.
 #[derive(Clone)]
 struct DrawBuffer {
     draw_commands: Vec<Command>,
-    consecutive_buffers: Vec<ConsecutiveBuffer>,
+    compiled_commands: Vec<CompiledCommand>,
     clear_command: Option<ClearInfo>,
     ready: bool
 }
@@ -70,167 +84,119 @@ impl DrawBuffer {
     fn new() -> DrawBuffer {
         DrawBuffer {
             draw_commands: Vec::new(),
-            consecutive_buffers: Vec::new(),
+            compiled_commands: Vec::new(),
             clear_command: None,
             ready: false 
         }
     }
 
+    fn is_empty(&self) -> bool {
+        self.clear_command.is_none() && self.draw_commands.is_empty()
+    }
+
+    /// Sort a run of draw commands by their 1 dimensional position and split it wherever
+    /// the style changes
+    fn compile_draws(draw_commands: &mut [command::DrawInfo]) -> Vec<ConsecutiveBuffer> {
+        // The sort is stable, so characters at the same position keep the order they were
+        // drawn in
+        draw_commands.sort_by_key(|draw| (draw.draw_pos_y, draw.draw_pos_x));
+
+        let mut consecutive_buffers: Vec<ConsecutiveBuffer> = Vec::new();
+        for draw in draw_commands.iter() {
+            match consecutive_buffers.last_mut() {
+                Some(buffer) if buffer.style == Style::from(draw) => buffer.draw_commands.push(*draw),
+                _ => {
+                    let mut buffer = ConsecutiveBuffer::new(Style::from(draw));
+                    buffer.draw_commands.push(*draw);
+                    consecutive_buffers.push(buffer);
+                }
+            }
+        }
+        consecutive_buffers
+    }
+
     /// Prepare an internal buffer for drawing. Order the draw commands in the requested
     /// ordering
     fn prepare(&mut self) {
         // If a draw buffer is ready, we wont recompile it.
-        // To compile a draw buffer, we sort every command such that x0<x1. Afterward,
-        // we find consecutive colours and add that to a meta-array that will store the
-        // colour and cursor position that represents it. We then will draw the characters 
-        // until we end the buffer
-        if !self.ready {
-            if self.draw_commands.is_empty() {
-                self.ready = true;
-                return;
-            }
-            // Algorithm:
-            // - Get all draw commands 
-            // - Sort draw commands by 1 dimensional index
-            // - Iterate through sorted commands 
-            // - While the current colour = last seen colour, add to a consecutive buffer 
-            // - If we see a new colour, push current consecutive buffer to queue and 
-            //      allocate new one 
-
-            // Get all draw commands
-            let mut max_width = 0;
-            let mut draw_commands: Vec<(&command::DrawInfo, usize)> = self.draw_commands
-                .iter()
-                .enumerate()
-                .filter_map(|(idx, c)| match c {
-                    Command::Draw(d) => {
-                        max_width = u16::max(max_width, d.draw_pos_x);
-                        Some((d, idx))
-                    },
-                    _ => None 
-                })
-                .collect();
-                
-            // Sort by 1d index
-            draw_commands.sort_by_key(|c| c.0.draw_pos_x + c.0.draw_pos_y * max_width);
-
-            // Iterate through sorted commands
-            let mut last_colour_seen = draw_commands[0].0.colour;
-            let mut working_consecutive_buffer = ConsecutiveBuffer::new();
-            working_consecutive_buffer.colour = last_colour_seen;
-            for (c, idx) in draw_commands.iter() {
-                // If our colour hasn't been seen, push this consecutive buffer to the vec
-                if c.colour != last_colour_seen {
-                    // end the current consecutive buffer
-                    working_consecutive_buffer.end = *idx;
-                    self.consecutive_buffers.push(working_consecutive_buffer);
-                    working_consecutive_buffer = ConsecutiveBuffer::new();
-
-                    // initialise state
-                    last_colour_seen = c.colour;
-                    working_consecutive_buffer.colour = last_colour_seen;
-                }
-
-                working_consecutive_buffer.draw_commands.push(**c);
-            }
+        if self.ready {
+            return;
+        }
 
-            // if we have a draw command, we wouldnt have pushed this to our draw buffers.
-            // so we push it now
-            if let Some((_, idx)) = draw_commands.last() {
-                working_consecutive_buffer.end = *idx;
-                working_consecutive_buffer.prepare(max_width);
-                self.consecutive_buffers.push(working_consecutive_buffer);
+        // Algorithm:
+        // - Iterate through the commands, collecting draw commands into a run
+        // - When we see any other command, compile the run we have and push the command
+        //      after it, so cursor moves and flushes happen between the same draws
+        // - Compiling a run sorts it by 1 dimensional index and splits it by style
+        let mut draw_run = Vec::new();
+        for command in self.draw_commands.iter() {
+            let compiled = match command {
+                Command::Draw(draw_info) => {
+                    draw_run.push(*draw_info);
+                    continue;
+                },
+                Command::SetCursorPosition(x, y) => CompiledCommand::SetCursorPosition(*x, *y),
+                Command::Flush => CompiledCommand::Flush,
+                Command::Clear(_) => unreachable!("Clear commands start a new draw buffer")
+            };
+
+            if !draw_run.is_empty() {
+                self.compiled_commands.push(CompiledCommand::Draw(DrawBuffer::compile_draws(&mut draw_run)));
+                draw_run.clear();
             }
-
-            self.ready = true;
+            self.compiled_commands.push(compiled);
         }
-    }
 
-    fn execute_clear<'a, T>(&self, stdout: &'a mut T, command: &ClearInfo) -> &'a mut T where
-        T: Write {
-        match command {
-            ClearInfo::All => stdout.queue(Clear(ClearType::All)).unwrap(),
-            ClearInfo::AfterCursor => stdout.queue(Clear(ClearType::FromCursorDown)).unwrap(),
-            ClearInfo::BeforeCursor => stdout.queue(Clear(ClearType::FromCursorUp)).unwrap(),
-            ClearInfo::CurrentLine => stdout.queue(Clear(ClearType::CurrentLine)).unwrap(),
-            ClearInfo::UntilNewLine => stdout.queue(Clear(ClearType::UntilNewLine)).unwrap(),
-            ClearInfo::After(x, y) => {
-                stdout
-                    .queue(cursor::SavePosition).unwrap()
-                    .queue(cursor::MoveTo(*x, *y)).unwrap()
-                    .queue(Clear(ClearType::FromCursorDown)).unwrap()
-                    .queue(cursor::RestorePosition).unwrap()
-            },
-            ClearInfo::Before(x, y) => {
-                stdout
-                    .queue(cursor::SavePosition).unwrap()
-                    .queue(cursor::MoveTo(*x, *y)).unwrap()
-                    .queue(Clear(ClearType::FromCursorUp)).unwrap()
-                    .queue(cursor::RestorePosition).unwrap()
-            },
-            ClearInfo::Line(row) => {
-                stdout
-                    .queue(cursor::SavePosition).unwrap()
-                    .queue(cursor::MoveToRow(*row)).unwrap()
-                    .queue(Clear(ClearType::CurrentLine)).unwrap()
-                    .queue(cursor::RestorePosition).unwrap()
-            },
-            ClearInfo::UntilNewLineFrom(x, y) => {
-                stdout 
-                    .queue(cursor::SavePosition).unwrap()
-                    .queue(cursor::MoveTo(*x, *y)).unwrap()
-                    .queue(Clear(ClearType::UntilNewLine)).unwrap()
-                    .queue(cursor::RestorePosition).unwrap()
-            }
+        if !draw_run.is_empty() {
+            self.compiled_commands.push(CompiledCommand::Draw(DrawBuffer::compile_draws(&mut draw_run)));
         }
-    }
 
-    fn execute_draw<'a, T>(&self, stdout: &'a mut T, command: &command::DrawInfo) -> &'a mut T where 
-        T: Write {
-        stdout
-            .queue(cursor::MoveTo(command.draw_pos_x, command.draw_pos_y)).unwrap()
-            .queue(Print(command.character)).unwrap()
+        self.ready = true;
     }
 
-    fn execute_cursor_move<'a, T>(&self, stdout: &'a mut T, command: (&u16, &u16)) -> &'a mut T where 
-        T: Write {
-        stdout.queue(cursor::MoveTo(*command.0, *command.1)).unwrap()
+    /// If the buffer leaves the cursor somewhere set explicitly, rather than wherever its
+    /// last draw did
+    fn ends_at_set_cursor(&self) -> bool {
+        self.draw_commands
+            .iter()
+            .rev()
+            .find(|command| !matches!(command, Command::Flush))
+            .is_some_and(|command| matches!(command, Command::SetCursorPosition(..)))
     }
 
-    fn execute_flush<'a, T>(&self, stdout: &'a mut T) -> &'a mut T where
-        T: Write {
-        stdout.flush().unwrap();
-        stdout
+    /// Remove every draw command that a clear would erase
+    fn purge(&mut self, clear_info: &ClearInfo) {
+        self.draw_commands.retain(|command| match command {
+            Command::Draw(draw_info) => !clear_info.clears(draw_info.draw_pos_x, draw_info.draw_pos_y),
+            _ => true
+        });
     }
 
-    fn execute<T>(&self, stdout: &mut T) where T: Write {
+    fn rasterise(&self, grid: &mut CellGrid) {
         if !self.ready {
             panic!("Attempting to use draw buffer when it has not been prepared!");
         }
-        let mut stdout_queued = stdout;
 
         if let Some(clear_info) = self.clear_command {
-            stdout_queued = self.execute_clear(stdout_queued, &clear_info);
+            grid.clear(&clear_info);
         }
 
-        let working_consecutive_buffer = &mut self.consecutive_buffers.iter();
-        for command in &self.draw_commands {
-            stdout_queued = match command {
-                Command::Draw(_) => {
-                    if let Some(buffer) = working_consecutive_buffer.next() {
-                        stdout_queued = buffer.execute(stdout_queued);
+        for command in &self.compiled_commands {
+            match command {
+                CompiledCommand::Draw(consecutive_buffers) => {
+                    for buffer in consecutive_buffers {
+                        buffer.rasterise(grid);
                     }
-                    stdout_queued
                 },
-                Command::SetCursorPosition(x, y) => self.execute_cursor_move(stdout_queued, (x, y)),
-                Command::Flush => self.execute_flush(stdout_queued),
-                _ => stdout_queued
+                CompiledCommand::SetCursorPosition(x, y) => grid.move_cursor(*x, *y),
+                // The whole frame is flushed at once when it is presented
+                CompiledCommand::Flush => {}
             }
         }
     }
 }
 
-/// A set of commands that will be executed at draw time to create images on the terminal
+/// A set of commands that are drawn into the frame at draw time to create images on the terminal
 #[derive(Clone)]
 pub struct CommandBuffer {
     draw_buffers: Vec<DrawBuffer>
@@ -243,9 +209,11 @@ impl CommandBuffer {
         }
     }
 
-    pub fn execute<T>(&self, stdout: &mut T) where T: Write {
+    /// Draw the commands into a grid the same way executing them would draw them on a
+    /// terminal, blending each draw over what is beneath it
+    pub fn rasterise(&self, grid: &mut CellGrid) {
         for draw_buffer in self.draw_buffers.iter() {
-            draw_buffer.execute(stdout)
+            draw_buffer.rasterise(grid)
         }
     }
 }
@@ -253,8 +221,7 @@ impl CommandBuffer {
 pub struct CommandBufferCreator<'a> {
     command_buffer: &'a mut CommandBuffer,
     draw_buffers: Vec<DrawBuffer>,
-    working_draw_buffer: DrawBuffer,
-    first_command: bool
+    working_draw_buffer: DrawBuffer
 }
 
 impl<'a> CommandBuffer {
@@ -262,23 +229,86 @@ impl<'a> CommandBuffer {
         CommandBufferCreator {
             command_buffer: self,
             draw_buffers: Vec::new(),
-            working_draw_buffer: DrawBuffer::new(),
-            first_command: true
+            working_draw_buffer: DrawBuffer::new()
         }
     }
 }
 
 impl<'a> CommandBufferCreator<'a> {
+    /// Remove the draws that a clear at a known position later on would erase, since they
+    /// never need to be drawn. A clear relative to the cursor depends on where the draws
+    /// before it left the cursor, so those draws are only removed if the buffer moves the
+    /// cursor somewhere known once it is done
+    fn purge_cleared_draws(draw_buffers: &mut [DrawBuffer]) {
+        let mut relative_clear_follows = false;
+        for index in (0..draw_buffers.len()).rev() {
+            let (before, after) = draw_buffers.split_at_mut(index + 1);
+            let draw_buffer = &mut before[index];
+            if !relative_clear_follows || draw_buffer.ends_at_set_cursor() {
+                for clear_info in after.iter().filter_map(|later| later.clear_command) {
+                    draw_buffer.purge(&clear_info);
+                }
+            }
+            // A buffer's own clear runs before its draws, so it only depends on the buffers
+            // before it
+            relative_clear_follows |= draw_buffer.clear_command.is_some_and(|clear| clear.is_relative_to_cursor());
+        }
+    }
+
     fn handle_clear_command(&mut self, clear_info: command::ClearInfo) {
+        // A clear straight after the same clear does nothing
+        if self.working_draw_buffer.draw_commands.is_empty() && self.working_draw_buffer.clear_command == Some(clear_info) {
+            return;
+        }
+
+        // Every clear starts a new draw buffer
+        if !self.working_draw_buffer.is_empty() {
+            let working_draw_buffer = std::mem::replace(&mut self.working_draw_buffer, DrawBuffer::new());
+            self.draw_buffers.push(working_draw_buffer);
+        }
+        self.working_draw_buffer.clear_command = Some(clear_info);
     }
 
     fn handle_draw_command(&mut self, draw_info: command::DrawInfo) {
+        // A later opaque draw to the same position within a draw buffer covers an earlier
+        // one, as long as there is nothing between them that depends on it. Translucent
+        // draws blend over what is beneath them, so the earlier draw has to stay
+        if draw_info.colour.a != 0xFF || draw_info.background.a != 0xFF {
+            self.working_draw_buffer.draw_commands.push(Command::Draw(draw_info));
+            return;
+        }
+
+        let position = (draw_info.draw_pos_x, draw_info.draw_pos_y);
+        for command in self.working_draw_buffer.draw_commands.iter_mut().rev() {
+            match command {
+                Command::Draw(previous) if (previous.draw_pos_x, previous.draw_pos_y) == position => {
+                    *previous = draw_info;
+                    return;
+                },
+                Command::Draw(_) => continue,
+                _ => break
+            }
+        }
+        self.working_draw_buffer.draw_commands.push(Command::Draw(draw_info));
     }
 
     fn handle_flush(&mut self) {
+        // Flushing twice in a row sends nothing the second time
+        if let Some(Command::Flush) = self.working_draw_buffer.draw_commands.last() {
+            return;
+        }
+        self.working_draw_buffer.draw_commands.push(Command::Flush);
     }
 
     fn handle_cursor_move(&mut self, cursor_coordinates: (u16, u16)) {
+        // Only the last of several cursor moves in a row matters
+        let (x, y) = cursor_coordinates;
+        if let Some(Command::SetCursorPosition(last_x, last_y)) = self.working_draw_buffer.draw_commands.last_mut() {
+            *last_x = x;
+            *last_y = y;
+            return;
+        }
+        self.working_draw_buffer.draw_commands.push(Command::SetCursorPosition(x, y));
     }
 
     /// Add a command to the command buffer for execution
@@ -289,48 +319,351 @@ impl<'a> CommandBufferCreator<'a> {
             Command::Flush => self.handle_flush(),
             Command::SetCursorPosition(x, y) => self.handle_cursor_move((x, y))
         }
-
-        if let Command::Clear(clear_info) = command {
-            if !self.first_command {
-                // If not the first command in this buffer, push the buffer to the queue
-                // and get ready to create a new one
-                self.draw_buffers.push(self.working_draw_buffer.clone());
-                self.working_draw_buffer = DrawBuffer::new();
-            }
-            // Push the clear command to the working draw buffer
-            self.working_draw_buffer.clear_command = Some(clear_info);
-        } else {
-            self.working_draw_buffer.draw_commands.push(command);
-        }
-        self.first_command = false;
-
         self
     }
 
     /// Compile the command buffer for execution
     pub fn compile(mut self) {
         // Step 1) Push working draw buffer onto queue 
-        if !self.first_command {
+        if !self.working_draw_buffer.is_empty() {
             self.draw_buffers.push(self.working_draw_buffer);
         }
 
-        // Step 2) Clear all draw buffers before an "All" clear 
-        let mut last_all_draw = 0;
-        for (idx, draw_buffer) in self.draw_buffers.iter().enumerate() {
-            if let Some(ClearInfo::All) = draw_buffer.clear_command {
-                last_all_draw = idx;
-            }
-        }
-        if last_all_draw > 0 {
-            self.draw_buffers = self.draw_buffers.drain(0..last_all_draw).collect();
+        // Step 2) Discard all draw buffers before the last "All" clear, since it erases
+        // everything they drew
+        let last_all_clear = self.draw_buffers
+            .iter()
+            .rposition(|draw_buffer| matches!(draw_buffer.clear_command, Some(ClearInfo::All)));
+        if let Some(last_all_clear) = last_all_clear {
+            self.draw_buffers.drain(..last_all_clear);
         }
 
+        // Step 3) Remove draws that a later clear at a known position erases
+        CommandBufferCreator::purge_cleared_draws(&mut self.draw_buffers);
+
         self.command_buffer.draw_buffers = self.draw_buffers;
 
-        // Step 3) Compile remaining draw buffers
+        // Step 4) Compile remaining draw buffers
         for draw_buffer in self.command_buffer.draw_buffers.iter_mut() {
             draw_buffer.prepare();
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::crossterm::cell_grid::Cell;
+    use crate::renderer::crossterm::command::DrawInfo;
+    use crate::renderer::crossterm::device_settings::ColourMode;
+    use crate::renderer::crossterm::virtual_terminal::VirtualTerminal;
+    use crossterm::{ QueueableCommand, cursor };
+    use crossterm::style::{ Attribute, Color, Print, SetAttribute, SetAttributes, SetBackgroundColor, SetForegroundColor };
+
+    fn draw(x: u16, y: u16, character: char, colour: Colour) -> Command {
+        Command::Draw(DrawInfo { colour, draw_pos_x: x, draw_pos_y: y, character, ..DrawInfo::default() })
+    }
+
+    fn compiled(commands: &[Command]) -> CommandBuffer {
+        let mut command_buffer = CommandBuffer::new();
+        let mut creator = command_buffer.add_commands();
+        for command in commands {
+            creator = creator.execute(*command);
+        }
+        creator.compile();
+        command_buffer
+    }
+
+    fn rasterised(commands: &[Command], width: u16, height: u16) -> CellGrid {
+        let mut grid = CellGrid::new(width, height, Colour::black());
+        compiled(commands).rasterise(&mut grid);
+        grid
+    }
+
+    /// What is sent to a terminal already showing a blank grid to show the commands
+    fn output(commands: &[Command], width: u16, height: u16) -> Vec<u8> {
+        let mut out = Vec::new();
+        rasterised(commands, width, height)
+            .diff(Some(&CellGrid::new(width, height, Colour::black())), ColourMode::Limited, &mut out)
+            .unwrap();
+        out
+    }
+
+    fn characters(grid: &CellGrid, y: u16, width: u16) -> String {
+        (0..width).map(|x| grid.get(x, y).unwrap().character).collect()
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(compiled(&[]).draw_buffers.is_empty());
+        assert!(output(&[], 4, 2).is_empty());
+    }
+
+    #[test]
+    fn test_consecutive_draws() {
+        // draws are sorted by position, but ones at the same position keep their order
+        let commands = [
+            draw(1, 0, 'b', Colour::red()),
+            draw(0, 0, 'a', Colour::red()),
+            draw(3, 0, 'c', Colour::red()),
+            draw(4, 0, 'd', Colour::blue()),
+            draw(0, 1, 'e', Colour::blue()),
+            Command::SetCursorPosition(0, 0),
+            draw(0, 1, 'f', Colour::rgba(0, 0xFF, 0, 0x80)),
+            draw(0, 1, 'g', Colour::green())
+        ];
+        let grid = rasterised(&commands, 5, 2);
+        assert_eq!(characters(&grid, 0, 5), "ab cd");
+        assert_eq!(characters(&grid, 1, 5), "g    ");
+        assert_eq!(grid.get(3, 0).unwrap().foreground, Colour::red());
+        assert_eq!(grid.get(4, 0).unwrap().foreground, Colour::blue());
+        assert_eq!(grid.get(0, 1).unwrap().foreground, Colour::green());
+    }
+
+    #[test]
+    fn test_all_clear_discards_earlier_buffers() {
+        let commands = [
+            draw(0, 0, 'a', Colour::white()),
+            Command::Clear(ClearInfo::Line(3)),
+            draw(1, 0, 'b', Colour::white()),
+            Command::Clear(ClearInfo::All),
+            draw(2, 0, 'c', Colour::white()),
+            Command::Clear(ClearInfo::All),
+            draw(3, 0, 'd', Colour::white())
+        ];
+        let command_buffer = compiled(&commands);
+        assert_eq!(command_buffer.draw_buffers.len(), 1);
+        assert_eq!(command_buffer.draw_buffers[0].draw_commands, vec![draw(3, 0, 'd', Colour::white())]);
+
+        let mut grid = CellGrid::new(4, 1, Colour::black());
+        grid.set(0, 0, Cell { character: 'x', ..Cell::blank(Colour::black()) });
+        command_buffer.rasterise(&mut grid);
+        assert_eq!(characters(&grid, 0, 4), "   d");
+    }
+
+    #[test]
+    fn test_redundant_commands() {
+        // only an opaque draw can replace an earlier one
+        let opaque = |character, colour| Command::Draw(DrawInfo {
+            colour,
+            background: Colour::black(),
+            character,
+            ..DrawInfo::default()
+        });
+        let commands = [
+            Command::Clear(ClearInfo::Line(2)),
+            Command::Clear(ClearInfo::Line(2)),
+            opaque('a', Colour::white()),
+            opaque('b', Colour::green()),
+            Command::SetCursorPosition(4, 4),
+            Command::SetCursorPosition(5, 5),
+            Command::Flush,
+            Command::Flush
+        ];
+        let command_buffer = compiled(&commands);
+        assert_eq!(command_buffer.draw_buffers.len(), 1);
+        let draw_buffer = &command_buffer.draw_buffers[0];
+        assert_eq!(draw_buffer.clear_command, Some(ClearInfo::Line(2)));
+        assert_eq!(draw_buffer.draw_commands, vec![
+            opaque('b', Colour::green()),
+            Command::SetCursorPosition(5, 5),
+            Command::Flush
+        ]);
+    }
+
+    #[test]
+    fn test_order_around_cursor_moves() {
+        // draws are only reordered between other commands, never across them, so a clear
+        // from the cursor starts after the last draw before it
+        let commands = [
+            Command::Clear(ClearInfo::All),
+            draw(5, 0, 'a', Colour::white()),
+            draw(0, 0, 'b', Colour::white()),
+            Command::SetCursorPosition(0, 2),
+            draw(3, 1, 'c', Colour::white()),
+            Command::Flush,
+            draw(1, 1, 'd', Colour::white()),
+            Command::Clear(ClearInfo::UntilNewLine)
+        ];
+        let grid = rasterised(&commands, 6, 2);
+        assert_eq!(characters(&grid, 0, 6), "b    a");
+        assert_eq!(characters(&grid, 1, 6), " d    ");
+    }
+
+    #[test]
+    fn test_clears_purge_draws() {
+        let commands = [
+            draw(0, 0, 'a', Colour::white()),
+            Command::Clear(ClearInfo::AfterCursor),
+            draw(0, 1, 'b', Colour::white()),
+            draw(3, 2, 'c', Colour::white()),
+            draw(2, 2, 'd', Colour::white()),
+            Command::Clear(ClearInfo::UntilNewLineFrom(3, 2)),
+            Command::Clear(ClearInfo::Line(0))
+        ];
+
+        // 'a' is kept since the cursor relative clear depends on where it leaves the cursor,
+        // while 'c' can go as no clear after it depends on the cursor
+        let command_buffer = compiled(&commands);
+        assert_eq!(command_buffer.draw_buffers[0].draw_commands, vec![draw(0, 0, 'a', Colour::white())]);
+        assert_eq!(command_buffer.draw_buffers[1].draw_commands, vec![
+            draw(0, 1, 'b', Colour::white()),
+            draw(2, 2, 'd', Colour::white())
+        ]);
+
+        let mut grid = CellGrid::new(4, 3, Colour::black());
+        command_buffer.rasterise(&mut grid);
+        assert_eq!(characters(&grid, 0, 4), "    ");
+        assert_eq!(characters(&grid, 1, 4), "b   ");
+        assert_eq!(characters(&grid, 2, 4), "  d ");
+    }
+
+    #[test]
+    fn test_purge_keeps_cursor_for_later_clears() {
+        // the clear from the cursor runs from wherever 'a' left it, so 'a' has to be drawn
+        // even though the line clear erases it. Otherwise the clear would start at the top
+        // and erase what was already on screen
+        let commands = [
+            draw(5, 5, 'a', Colour::white()),
+            Command::Clear(ClearInfo::Line(5)),
+            Command::Clear(ClearInfo::AfterCursor)
+        ];
+        let command_buffer = compiled(&commands);
+        assert_eq!(command_buffer.draw_buffers[0].draw_commands, vec![draw(5, 5, 'a', Colour::white())]);
+
+        let mut grid = CellGrid::new(8, 8, Colour::black());
+        grid.set(0, 4, Cell { character: 'x', ..Cell::blank(Colour::black()) });
+        grid.set(7, 6, Cell { character: 'y', ..Cell::blank(Colour::black()) });
+        command_buffer.rasterise(&mut grid);
+        assert_eq!(characters(&grid, 4, 8), "x       ");
+        assert_eq!(characters(&grid, 5, 8), "        ");
+        assert_eq!(characters(&grid, 6, 8), "        ");
+
+        // once the cursor is moved somewhere known afterwards, 'a' doesn't matter to it
+        let commands = [
+            draw(5, 5, 'a', Colour::white()),
+            Command::SetCursorPosition(6, 5),
+            Command::Clear(ClearInfo::Line(5)),
+            Command::Clear(ClearInfo::AfterCursor)
+        ];
+        assert_eq!(compiled(&commands).draw_buffers[0].draw_commands, vec![Command::SetCursorPosition(6, 5)]);
+    }
+
+    #[test]
+    fn test_purge_overlay_lines() {
+        // an overlay draws its lines one after another and then blanks a line it no longer
+        // needs, without ever moving the cursor itself
+        let lines = ["fps 60", "update 1.20ms", "render 0.40ms"];
+        let mut commands = Vec::new();
+        for (y, line) in lines.iter().enumerate() {
+            for (x, character) in line.chars().enumerate() {
+                commands.push(Command::Draw(DrawInfo {
+                    colour: Colour::yellow(),
+                    draw_pos_x: x as u16,
+                    draw_pos_y: y as u16,
+                    character,
+                    ..DrawInfo::default()
+                }));
+            }
+        }
+        commands.push(Command::Clear(ClearInfo::Line(1)));
+
+        let command_buffer = compiled(&commands);
+        let draw_buffer = &command_buffer.draw_buffers[0];
+        assert_eq!(draw_buffer.draw_commands.len(), lines[0].len() + lines[2].len());
+        assert!(draw_buffer.draw_commands.iter().all(|command| !matches!(command, Command::Draw(DrawInfo { draw_pos_y: 1, .. }))));
+
+        let grid = rasterised(&commands, 13, 3);
+        assert_eq!(characters(&grid, 0, 13), "fps 60       ");
+        assert_eq!(characters(&grid, 1, 13), "             ");
+        assert_eq!(characters(&grid, 2, 13), "render 0.40ms");
+    }
+
+    #[test]
+    fn test_shown_on_terminal() {
+        let commands = [
+            Command::Clear(ClearInfo::All),
+            draw(0, 0, 'a', Colour::red()),
+            draw(5, 0, 'b', Colour::blue()),
+            draw(2, 1, 'c', Colour::green()),
+            draw(3, 1, 'd', Colour::green()),
+            Command::Flush,
+            Command::SetCursorPosition(1, 0),
+            Command::Clear(ClearInfo::UntilNewLine),
+            draw(4, 2, 'e', Colour::red()),
+            Command::Clear(ClearInfo::Before(2, 1)),
+            draw(0, 2, 'f', Colour::yellow())
+        ];
+        let grid = rasterised(&commands, 6, 3);
+        let mut terminal = VirtualTerminal::new(6, 3);
+        grid.diff(None, ColourMode::Limited, &mut terminal).unwrap();
+
+        assert_eq!(terminal.snapshot(), "\n   d\nf   e");
+        assert_eq!(terminal.cell(3, 1).unwrap().foreground, Color::Green);
+        grid.assert_shown_on(&terminal, ColourMode::Limited);
+    }
+
+    #[test]
+    fn test_styles() {
+        // the style only changes where it has to, and attributes are reset to be removed
+        let bold = Command::Draw(DrawInfo {
+            colour: Colour::red(),
+            attributes: Attribute::Bold.into(),
+            draw_pos_x: 1,
+            character: 'b',
+            ..DrawInfo::default()
+        });
+        let highlighted = Command::Draw(DrawInfo {
+            colour: Colour::red(),
+            background: Colour::blue(),
+            draw_pos_x: 2,
+            character: 'c',
+            ..DrawInfo::default()
+        });
+        let commands = [draw(0, 0, 'a', Colour::red()), bold, highlighted, draw(3, 0, 'd', Colour::red())];
+
+        let mut expected = Vec::new();
+        expected
+            .queue(SetAttribute(Attribute::Reset)).unwrap()
+            .queue(cursor::MoveTo(0, 0)).unwrap()
+            .queue(SetForegroundColor(Color::Red)).unwrap()
+            .queue(SetBackgroundColor(Color::Black)).unwrap()
+            .queue(Print('a')).unwrap()
+            .queue(SetAttribute(Attribute::Reset)).unwrap()
+            .queue(SetAttributes(Attribute::Bold.into())).unwrap()
+            .queue(SetForegroundColor(Color::Red)).unwrap()
+            .queue(SetBackgroundColor(Color::Black)).unwrap()
+            .queue(Print('b')).unwrap()
+            .queue(SetAttribute(Attribute::Reset)).unwrap()
+            .queue(SetForegroundColor(Color::Red)).unwrap()
+            .queue(SetBackgroundColor(Color::Blue)).unwrap()
+            .queue(Print('c')).unwrap()
+            .queue(SetBackgroundColor(Color::Black)).unwrap()
+            .queue(Print('d')).unwrap()
+            .queue(SetAttribute(Attribute::Reset)).unwrap();
+        assert_eq!(output(&commands, 4, 1), expected);
+
+        let grid = rasterised(&commands, 4, 1);
+        assert_eq!(grid.get(1, 0).unwrap().attributes, Attribute::Bold.into());
+        assert_eq!(grid.get(2, 0).unwrap().background, Colour::blue());
+        assert_eq!(grid.get(3, 0), Some(&Cell { character: 'd', foreground: Colour::red(), ..Cell::blank(Colour::black()) }));
+    }
 
+    #[test]
+    fn test_translucent_draws_kept() {
+        // a tint over a glyph in the same buffer blends over it rather than replacing it
+        let commands = [
+            draw(1, 0, '@', Colour::yellow()),
+            Command::Draw(DrawInfo {
+                colour: Colour::rgba(0, 0, 0, 0),
+                background: Colour::rgba(0, 0, 0, 0x80),
+                draw_pos_x: 1,
+                ..DrawInfo::default()
+            })
+        ];
+        let grid = rasterised(&commands, 2, 1);
+        let cell = grid.get(1, 0).unwrap();
+        assert_eq!(cell.character, '@');
+        assert_eq!(cell.foreground, Colour::rgba(0, 0, 0, 0x80).blend_over(&Colour::yellow()));
     }
 }
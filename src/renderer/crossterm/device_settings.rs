@@ -16,10 +16,146 @@
     along with this program.  if not, see <https://www.gnu.org/licenses/>.
 */
 use crate::renderer::colours::Colour;
+use serde::{ Serialize, Deserialize };
+use std::fmt;
+use std::str::FromStr;
+
+/// How colours are sent to the terminal
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColourMode {
+    /// Work out what the terminal supports from the environment when starting
+    #[default]
+    Auto,
+    /// Don't send any colours, leaving everything in the terminal's own colours
+    Monochrome,
+    /// Map every colour to the closest of the 16 standard terminal colours
+    Limited,
+    /// Map every colour to the closest of the xterm 256 colour palette
+    Ansi256,
+    /// Send colours as 24 bit RGB
+    TrueColour
+}
+
+impl ColourMode {
+    /// The colour mode the terminal we are running in supports
+    pub fn detect() -> ColourMode {
+        ColourMode::detect_from(|name| std::env::var(name).ok())
+    }
+
+    /// Work out the colour mode from environment variables. There is no reliable way to
+    /// ask a terminal what it supports, so we go by the conventions most terminals follow
+    fn detect_from<F>(var: F) -> ColourMode where
+        F: Fn(&str) -> Option<String> {
+        // https://no-color.org
+        if var("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return ColourMode::Monochrome;
+        }
+
+        let colour_term = var("COLORTERM").unwrap_or_default().to_lowercase();
+        if colour_term == "truecolor" || colour_term == "24bit" {
+            return ColourMode::TrueColour;
+        }
+
+        // Windows Terminal doesn't set TERM, but always supports RGB
+        if var("WT_SESSION").is_some() {
+            return ColourMode::TrueColour;
+        }
+
+        match var("TERM_PROGRAM").as_deref() {
+            Some("iTerm.app") | Some("WezTerm") | Some("vscode") => return ColourMode::TrueColour,
+            Some("Apple_Terminal") => return ColourMode::Ansi256,
+            _ => {}
+        }
+
+        match var("TERM").map(|term| term.to_lowercase()) {
+            None => ColourMode::Limited,
+            Some(term) if term == "dumb" => ColourMode::Monochrome,
+            Some(term) if term.contains("truecolor") || term.contains("24bit") || term.ends_with("-direct") => ColourMode::TrueColour,
+            Some(term) if term.contains("256color") => ColourMode::Ansi256,
+            Some(_) => ColourMode::Limited
+        }
+    }
+
+    /// Detect the colour mode if it is automatic, otherwise keep the mode we were given
+    pub fn resolve(self) -> ColourMode {
+        match self {
+            ColourMode::Auto => {
+                let detected = ColourMode::detect();
+                log::info!("Detected colour mode {}", detected);
+                detected
+            },
+            colour_mode => colour_mode
+        }
+    }
+}
+
+impl FromStr for ColourMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(ColourMode::Auto),
+            "monochrome" | "mono" | "none" => Ok(ColourMode::Monochrome),
+            "limited" | "16" => Ok(ColourMode::Limited),
+            "ansi256" | "ansi-256" | "256" => Ok(ColourMode::Ansi256),
+            "true-colour" | "true-color" | "truecolour" | "truecolor" | "rgb" => Ok(ColourMode::TrueColour),
+            _ => Err(format!("\"{}\" is not a colour mode", s))
+        }
+    }
+}
+
+impl fmt::Display for ColourMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColourMode::Auto => write!(f, "auto"),
+            ColourMode::Monochrome => write!(f, "monochrome"),
+            ColourMode::Limited => write!(f, "limited"),
+            ColourMode::Ansi256 => write!(f, "ansi256"),
+            ColourMode::TrueColour => write!(f, "true-colour")
+        }
+    }
+}
 
 pub struct DeviceSettings {
     pub swapchain_count: usize,
     pub display_length_x: u16,
     pub display_length_y: u16,
-    pub clear_colour: Colour
+    pub clear_colour: Colour,
+    pub colour_mode: ColourMode
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn detect(vars: &[(&str, &str)]) -> ColourMode {
+        let vars: HashMap<String, String> = vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        ColourMode::detect_from(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(&[]), ColourMode::Limited);
+        assert_eq!(detect(&[("TERM", "xterm")]), ColourMode::Limited);
+        assert_eq!(detect(&[("TERM", "xterm-256color")]), ColourMode::Ansi256);
+        assert_eq!(detect(&[("TERM", "xterm-256color"), ("COLORTERM", "truecolor")]), ColourMode::TrueColour);
+        assert_eq!(detect(&[("TERM", "xterm-direct")]), ColourMode::TrueColour);
+        assert_eq!(detect(&[("TERM", "dumb")]), ColourMode::Monochrome);
+        assert_eq!(detect(&[("TERM", "xterm-256color"), ("NO_COLOR", "1")]), ColourMode::Monochrome);
+        assert_eq!(detect(&[("TERM", "xterm-256color"), ("NO_COLOR", "")]), ColourMode::Ansi256);
+        assert_eq!(detect(&[("WT_SESSION", "id")]), ColourMode::TrueColour);
+    }
+
+    #[test]
+    fn test_parse() {
+        for colour_mode in [ColourMode::Auto, ColourMode::Monochrome, ColourMode::Limited, ColourMode::Ansi256, ColourMode::TrueColour] {
+            assert_eq!(colour_mode.to_string().parse::<ColourMode>(), Ok(colour_mode));
+            let json = serde_json::to_string(&colour_mode).unwrap();
+            assert_eq!(serde_json::from_str::<ColourMode>(&json).unwrap(), colour_mode);
+        }
+        assert_eq!("256".parse::<ColourMode>(), Ok(ColourMode::Ansi256));
+        assert!("lots".parse::<ColourMode>().is_err());
+    }
 }
@@ -15,49 +15,98 @@
     you should have received a copy of the gnu general public license
     along with this program.  if not, see <https://www.gnu.org/licenses/>.
 */
+use crate::renderer::crossterm::cell_grid::CellGrid;
 use crate::renderer::crossterm::swapchain::Framebuffer;
-use std::sync::{
-    Arc, Mutex, Condvar,
-    atomic::{ AtomicBool, Ordering }
-};
+use crate::engine_temp::frame_pipeline::FramePipeline;
+use std::io::Write;
+use std::sync::{ Arc, Mutex };
 use std::thread;
 
-pub struct DisplayEngine {
-    framebuffers: Vec<Arc<Mutex<Framebuffer>>>,
-    ready: Arc<Condvar>,
-    render_thread: Option<thread::JoinHandle<()>>,
-    running: Arc<AtomicBool>
+pub struct DisplayEngine<W> where
+    W: Write + Send + 'static {
+    framebuffers: Vec<Arc<Mutex<Framebuffer<W>>>>,
+    frame_pipeline: FramePipeline,
+    render_thread: Option<thread::JoinHandle<()>>
 }
 
-impl DisplayEngine {
-    pub fn new(framebuffers: Vec<Arc<Mutex<Framebuffer>>>, ready: Arc<Condvar>) -> DisplayEngine {
+impl<W> DisplayEngine<W> where
+    W: Write + Send + 'static {
+    pub fn new(framebuffers: Vec<Arc<Mutex<Framebuffer<W>>>>, frame_pipeline: FramePipeline) -> DisplayEngine<W> {
         DisplayEngine {
             framebuffers: framebuffers.clone(),
-            ready,
-            render_thread: None,
-            running: Arc::new(AtomicBool::new(false))
+            frame_pipeline,
+            render_thread: None
         }
     }
 
     pub fn run(&mut self) {
-        let running = self.running.clone();
-        let ready = self.ready.clone();
+        let frame_pipeline = self.frame_pipeline.clone();
         let framebuffers = self.framebuffers.clone();
 
         self.render_thread = Some(thread::spawn(move || {
-            running.store(true, Ordering::SeqCst);
-            while running.load(Ordering::SeqCst) {
-                // we wait until we are signalled to render. Until then we can't assume there 
-                // is data in the framebuffer
-                let working_index = Mutex::new(0);
-                let mut locked_index = working_index.lock().unwrap();
-                locked_index = ready.wait(locked_index).unwrap();
-                framebuffers[*locked_index].lock().unwrap().flush()
+            // we wait until a frame is presented. Until then we can't assume there is data
+            // in the framebuffer. Any frame presented before we were stopped is still flushed
+            let mut presented: Option<CellGrid> = None;
+            while let Some(frame) = frame_pipeline.acquire() {
+                let index = (frame % framebuffers.len() as u64) as usize;
+                framebuffers[index].lock().unwrap().flush(&mut presented);
+                frame_pipeline.release();
             }
         }));
     }
 
+    /// Stop the render thread once it has flushed the last presented frame, and wait
+    /// for it to finish
     pub fn stop(&mut self) {
-        self.running.store(false, Ordering::SeqCst);
+        self.frame_pipeline.close();
+        if let Some(render_thread) = self.render_thread.take() {
+            if render_thread.join().is_err() {
+                log::error!("Render thread panicked");
+            }
+        }
+    }
+}
+
+impl<W> Drop for DisplayEngine<W> where
+    W: Write + Send + 'static {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::colours::Colour;
+    use crate::renderer::crossterm::device_settings::{ ColourMode, DeviceSettings };
+    use crate::renderer::crossterm::swapchain::Swapchain;
+    use crate::renderer::crossterm::virtual_terminal::VirtualTerminal;
+
+    #[test]
+    fn test_stop_wakes_render_thread() {
+        let terminal = Arc::new(Mutex::new(VirtualTerminal::new(4, 1)));
+        let mut swapchain = Swapchain::new(&DeviceSettings {
+            swapchain_count: 2,
+            display_length_x: 4,
+            display_length_y: 1,
+            clear_colour: Colour::black(),
+            colour_mode: ColourMode::Limited
+        }, terminal.clone());
+        let mut display = DisplayEngine::new(
+            swapchain.framebuffers.clone(),
+            swapchain.frame_pipeline.clone()
+        );
+
+        // stopping while waiting for a frame must not block forever
+        display.run();
+        swapchain.swap();
+        display.stop();
+        assert!(display.render_thread.is_none());
+        assert_eq!(swapchain.frame_pipeline.frames_in_flight(), 0);
+
+        assert_eq!(terminal.lock().unwrap().flushes(), 1);
+
+        // stopping twice is fine
+        display.stop();
     }
 }
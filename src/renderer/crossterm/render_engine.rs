@@ -16,40 +16,152 @@
     along with this program.  if not, see <https://www.gnu.org/licenses/>.
 */
 use crate::renderer::crossterm::{
-    colour,
+    command_buffer::CommandBuffer,
     device_settings::DeviceSettings,
     swapchain::Swapchain,
     display_engine::DisplayEngine
 };
-use crossterm::style::Color;
+use crate::engine_temp::frame_pipeline::FramePipeline;
+use std::io::{ Stdout, Write, stdout };
+use std::sync::{ Arc, Mutex };
 
-struct Display {
-    engine: DisplayEngine,
-    width: u16,
-    height: u16
-}
-
-pub struct RenderEngine {
-    swapchain: Swapchain,
-    display: Display,
-    clear_colour: Color
+/// Draws command buffers to a target. By default this is the terminal through stdout, but
+/// anything that can be written to works
+pub struct RenderEngine<W = Stdout> where
+    W: Write + Send + 'static {
+    swapchain: Swapchain<W>,
+    display_engine: DisplayEngine<W>
 }
 
 impl RenderEngine {
     pub fn new(settings: DeviceSettings) -> RenderEngine {
-        let swapchain = Swapchain::new(settings.swapchain_count);
+        RenderEngine::with_target(settings, Arc::new(Mutex::new(stdout())))
+    }
+}
+
+impl<W> RenderEngine<W> where
+    W: Write + Send + 'static {
+    /// Render to a shared target, such as a virtual terminal, instead of stdout
+    pub fn with_target(settings: DeviceSettings, target: Arc<Mutex<W>>) -> RenderEngine<W> {
+        let swapchain = Swapchain::new(&settings, target);
         RenderEngine {
-            display: Display {
-                engine: DisplayEngine::new(swapchain.framebuffers.clone(), swapchain.swapped.clone()),
-                width: settings.display_length_x,
-                height: settings.display_length_y
-            },
-            swapchain,
-            clear_colour: colour::map_to_limited_colours(&settings.clear_colour)
+            display_engine: DisplayEngine::new(
+                swapchain.framebuffers.clone(),
+                swapchain.frame_pipeline.clone()
+            ),
+            swapchain
         }
     }
 
     pub fn init(&mut self) {
-        self.display.engine.run();
+        self.display_engine.run();
+    }
+
+    /// The pipeline frames are presented through. The game should wait on it before
+    /// starting a new frame so it doesn't get ahead of the display
+    pub fn frame_pipeline(&self) -> FramePipeline {
+        self.swapchain.frame_pipeline.clone()
+    }
+
+    /// Stop the display once it has flushed every presented frame
+    pub fn shutdown(&mut self) {
+        self.display_engine.stop();
+    }
+
+    /// Change the size of the display we are drawing to
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.swapchain.resize(width, height);
+    }
+
+    /// Queue a command buffer to be drawn to the current frame
+    pub fn submit(&mut self, commands: &CommandBuffer) {
+        self.swapchain.queue_commands(commands);
+    }
+
+    /// Finish the current frame and hand it to the display
+    pub fn present(&mut self) {
+        self.swapchain.swap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::colours::Colour;
+    use crate::renderer::crossterm::command::{ Command, ClearInfo, DrawInfo };
+    use crate::renderer::crossterm::device_settings::ColourMode;
+    use crate::renderer::crossterm::virtual_terminal::VirtualTerminal;
+    use crossterm::style::Color;
+
+    fn draw_text(commands: &mut CommandBuffer, x: u16, y: u16, text: &str) {
+        let mut creator = commands.add_commands().execute(Command::Clear(ClearInfo::All));
+        for (offset, character) in text.chars().enumerate() {
+            creator = creator.execute(Command::Draw(DrawInfo {
+                colour: Colour::green(),
+                draw_pos_x: x + offset as u16,
+                draw_pos_y: y,
+                character,
+                ..DrawInfo::default()
+            }));
+        }
+        creator.compile();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let terminal = Arc::new(Mutex::new(VirtualTerminal::new(10, 3)));
+        let mut render_engine = RenderEngine::with_target(DeviceSettings {
+            swapchain_count: 2,
+            display_length_x: 10,
+            display_length_y: 3,
+            clear_colour: Colour::black(),
+            colour_mode: ColourMode::Limited
+        }, terminal.clone());
+        render_engine.init();
+        let frame_pipeline = render_engine.frame_pipeline();
+
+        let mut commands = CommandBuffer::new();
+        draw_text(&mut commands, 2, 1, "hello");
+        render_engine.submit(&commands);
+        render_engine.present();
+        frame_pipeline.wait_for_slot();
+
+        draw_text(&mut commands, 0, 2, "bye");
+        render_engine.submit(&commands);
+        render_engine.present();
+        render_engine.shutdown();
+
+        let terminal = terminal.lock().unwrap();
+        assert_eq!(terminal.snapshot(), "\n\nbye");
+        assert_eq!(terminal.cell(0, 2).unwrap().foreground, Color::Green);
+        assert_eq!(terminal.flushes(), 2);
+    }
+
+    #[test]
+    fn test_resize() {
+        // draws past the old size only show once the engine has been resized
+        let terminal = Arc::new(Mutex::new(VirtualTerminal::new(12, 4)));
+        let mut render_engine = RenderEngine::with_target(DeviceSettings {
+            swapchain_count: 2,
+            display_length_x: 10,
+            display_length_y: 3,
+            clear_colour: Colour::black(),
+            colour_mode: ColourMode::Limited
+        }, terminal.clone());
+        render_engine.init();
+        let frame_pipeline = render_engine.frame_pipeline();
+
+        let mut commands = CommandBuffer::new();
+        draw_text(&mut commands, 9, 3, "abc");
+        render_engine.submit(&commands);
+        render_engine.present();
+        frame_pipeline.wait_for_slot();
+        assert_eq!(terminal.lock().unwrap().snapshot(), "\n\n\n");
+
+        render_engine.resize(12, 4);
+        render_engine.submit(&commands);
+        render_engine.present();
+        render_engine.shutdown();
+        assert_eq!(terminal.lock().unwrap().snapshot(), "\n\n\n         abc");
     }
 }
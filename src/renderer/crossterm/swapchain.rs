@@ -15,47 +15,95 @@
     you should have received a copy of the gnu general public license
     along with this program.  if not, see <https://www.gnu.org/licenses/>.
 */
-use std::io::{ Write, BufWriter, Stdout, stdout };
-use std::sync::{ Mutex, Arc, Condvar };
+use std::io::Write;
+use std::sync::{ Mutex, Arc };
+use crate::engine_temp::frame_pipeline::FramePipeline;
+use crate::renderer::crossterm::cell_grid::CellGrid;
 use crate::renderer::crossterm::command_buffer::CommandBuffer;
+use crate::renderer::crossterm::device_settings::{ ColourMode, DeviceSettings };
 
-pub struct Framebuffer(BufWriter<Stdout>);
-impl Framebuffer {
-    fn new() -> Framebuffer {
-        Framebuffer(BufWriter::new(stdout()))
+/// A single frame. Only the cells that changed since the last presented frame are written
+/// to the target, all at once so frames never interleave
+pub struct Framebuffer<W> where
+    W: Write {
+    grid: CellGrid,
+    colour_mode: ColourMode,
+    /// The bytes of the last diff, kept so we don't reallocate every frame
+    commands: Vec<u8>,
+    target: Arc<Mutex<W>>
+}
+
+impl<W> Framebuffer<W> where
+    W: Write {
+    fn new(settings: &DeviceSettings, target: Arc<Mutex<W>>) -> Framebuffer<W> {
+        Framebuffer {
+            grid: CellGrid::new(settings.display_length_x, settings.display_length_y, settings.clear_colour),
+            colour_mode: settings.colour_mode,
+            commands: Vec::new(),
+            target
+        }
     }
 
-    pub fn flush(&mut self) {
-        self.0.flush().unwrap()
+    /// Write this frame to the target and make it the presented frame
+    pub fn flush(&mut self, presented: &mut Option<CellGrid>) {
+        self.commands.clear();
+        let written = self.grid.diff(presented.as_ref(), self.colour_mode, &mut self.commands)
+            .and_then(|_| {
+                let mut target = self.target.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                target.write_all(&self.commands).and_then(|_| target.flush())
+            });
+
+        match written {
+            Ok(_) => match presented {
+                Some(presented) => presented.clone_from(&self.grid),
+                None => *presented = Some(self.grid.clone())
+            },
+            Err(e) => {
+                // We don't know what made it to the display, so the next frame redraws everything
+                log::error!("Could not write frame to the display: {}", e);
+                *presented = None;
+            }
+        }
     }
 }
 
-pub struct Swapchain {
-    pub framebuffers: Vec<Arc<Mutex<Framebuffer>>>,
-    pub swapped: Arc<Condvar>,
-    pub working_framebuffer: Arc<Mutex<usize>>,
+pub struct Swapchain<W> where
+    W: Write {
+    pub framebuffers: Vec<Arc<Mutex<Framebuffer<W>>>>,
+    pub frame_pipeline: FramePipeline,
+    /// What the game has drawn so far. Commands draw over the previous frame like they
+    /// would on a terminal, and a copy is handed to a framebuffer when swapping
+    back_buffer: CellGrid,
+    working_framebuffer: usize
 }
 
-impl Swapchain {
-    pub fn new(framebuffer_count: usize) -> Swapchain {
+impl<W> Swapchain<W> where
+    W: Write {
+    pub fn new(settings: &DeviceSettings, target: Arc<Mutex<W>>) -> Swapchain<W> {
         let mut buffers = Vec::new();
-        buffers.resize_with(framebuffer_count, || Arc::new(Mutex::new(Framebuffer::new())));
+        buffers.resize_with(settings.swapchain_count, || Arc::new(Mutex::new(Framebuffer::new(settings, target.clone()))));
         Swapchain {
             framebuffers: buffers,
-            working_framebuffer: Arc::new(Mutex::new(0)),
-            swapped: Arc::new(Condvar::new())
+            // One framebuffer is always being written to, so the rest can be in flight
+            frame_pipeline: FramePipeline::new(settings.swapchain_count.saturating_sub(1)),
+            back_buffer: CellGrid::new(settings.display_length_x, settings.display_length_y, settings.clear_colour),
+            working_framebuffer: 0
         }
     }
 
+    /// Change the size of the frames. The next frame is drawn from scratch
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.back_buffer.resize(width, height);
+    }
+
     pub fn queue_commands(&mut self, commands: &CommandBuffer) {
-        let working_framebuffer = self.working_framebuffer.lock().unwrap();
-        let mut framebuffer = self.framebuffers[*working_framebuffer].lock().unwrap();
-        commands.execute(&mut framebuffer.0);
+        commands.rasterise(&mut self.back_buffer);
     }
 
     pub fn swap(&mut self) {
-        let mut working_framebuffer = self.working_framebuffer.lock().unwrap();
-        self.swapped.notify_all();
-        *working_framebuffer = (*working_framebuffer + 1) % self.framebuffers.len();
+        self.framebuffers[self.working_framebuffer].lock().unwrap().grid.clone_from(&self.back_buffer);
+        let frame = self.frame_pipeline.submit();
+        debug_assert_eq!(frame % self.framebuffers.len() as u64, self.working_framebuffer as u64);
+        self.working_framebuffer = (self.working_framebuffer + 1) % self.framebuffers.len();
     }
 }
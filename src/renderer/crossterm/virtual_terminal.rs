@@ -0,0 +1,499 @@
+/*
+    a roguelike game created for a fun exercise
+    copyright (c) 2023  bailey danyluk
+
+    this program is free software: you can redistribute it and/or modify
+    it under the terms of the gnu general public license as published by
+    the free software foundation, either version 3 of the license, or
+    (at your option) any later version.
+
+    this program is distributed in the hope that it will be useful,
+    but without any warranty; without even the implied warranty of
+    merchantability or fitness for a particular purpose.  see the
+    gnu general public license for more details.
+
+    you should have received a copy of the gnu general public license
+    along with this program.  if not, see <https://www.gnu.org/licenses/>.
+*/
+use crossterm::style::{ Attribute, Attributes, Color };
+use std::io::{ self, Write };
+
+/// A single character on the virtual terminal's screen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub character: char,
+    pub foreground: Color,
+    pub background: Color,
+    pub attributes: Attributes
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        Cell {
+            character: ' ',
+            foreground: Color::Reset,
+            background: Color::Reset,
+            attributes: Attributes::default()
+        }
+    }
+}
+
+/// Where we are in an escape sequence
+#[derive(Clone, Debug, PartialEq)]
+enum ParseState {
+    Ground,
+    Escape,
+    /// A control sequence, `ESC [`, with the parameter bytes seen so far
+    Csi(String)
+}
+
+/// An in-memory terminal. Anything written to it is interpreted as the ANSI stream a real
+/// terminal would receive and drawn into a grid of cells, so rendered frames can be
+/// inspected without a TTY. Only the sequences the renderer emits are understood; anything
+/// else is ignored
+pub struct VirtualTerminal {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+    cursor: (u16, u16),
+    saved_cursor: (u16, u16),
+    /// Set after printing to the last column. The next character wraps to a new line first
+    pending_wrap: bool,
+    foreground: Color,
+    background: Color,
+    attributes: Attributes,
+    state: ParseState,
+    /// Bytes of a UTF-8 character we haven't seen all of yet
+    partial_character: Vec<u8>,
+    flushes: usize
+}
+
+/// Map the 16 standard colour indices to crossterm's named colours
+fn indexed_colour(index: u8) -> Color {
+    match index {
+        0 => Color::Black,
+        1 => Color::DarkRed,
+        2 => Color::DarkGreen,
+        3 => Color::DarkYellow,
+        4 => Color::DarkBlue,
+        5 => Color::DarkMagenta,
+        6 => Color::DarkCyan,
+        7 => Color::Grey,
+        8 => Color::DarkGrey,
+        9 => Color::Red,
+        10 => Color::Green,
+        11 => Color::Yellow,
+        12 => Color::Blue,
+        13 => Color::Magenta,
+        14 => Color::Cyan,
+        15 => Color::White,
+        _ => Color::AnsiValue(index)
+    }
+}
+
+impl VirtualTerminal {
+    pub fn new(width: u16, height: u16) -> VirtualTerminal {
+        VirtualTerminal {
+            width,
+            height,
+            cells: vec![Cell::default(); width as usize * height as usize],
+            cursor: (0, 0),
+            saved_cursor: (0, 0),
+            pending_wrap: false,
+            foreground: Color::Reset,
+            background: Color::Reset,
+            attributes: Attributes::default(),
+            state: ParseState::Ground,
+            partial_character: Vec::new(),
+            flushes: 0
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn cursor(&self) -> (u16, u16) {
+        self.cursor
+    }
+
+    /// How many times the terminal has been flushed
+    pub fn flushes(&self) -> usize {
+        self.flushes
+    }
+
+    pub fn cell(&self, x: u16, y: u16) -> Option<&Cell> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.cells.get(y as usize * self.width as usize + x as usize)
+    }
+
+    /// The characters on a row, with trailing blanks removed
+    pub fn row_text(&self, y: u16) -> Option<String> {
+        if y >= self.height {
+            return None;
+        }
+        let start = y as usize * self.width as usize;
+        let row: String = self.cells[start..start + self.width as usize]
+            .iter()
+            .map(|cell| cell.character)
+            .collect();
+        Some(row.trim_end().to_string())
+    }
+
+    /// Every row of the screen, one per line. Meant for comparing against expected frames
+    pub fn snapshot(&self) -> String {
+        (0..self.height)
+            .filter_map(|y| self.row_text(y))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn move_cursor(&mut self, x: u16, y: u16) {
+        self.cursor = (x.min(self.width.saturating_sub(1)), y.min(self.height.saturating_sub(1)));
+        self.pending_wrap = false;
+    }
+
+    /// Like most terminals, cleared cells take the current background colour
+    fn clear_cells(&mut self, start: usize, end: usize) {
+        let end = end.min(self.cells.len());
+        if start < end {
+            self.cells[start..end].fill(Cell {
+                background: self.background,
+                ..Cell::default()
+            });
+        }
+    }
+
+    fn index(&self, x: u16, y: u16) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    /// Move everything up a line, leaving an empty line at the bottom
+    fn scroll(&mut self) {
+        let width = self.width as usize;
+        self.cells.drain(..width);
+        self.cells.extend(std::iter::repeat_n(Cell::default(), width));
+    }
+
+    fn line_feed(&mut self) {
+        if self.cursor.1 + 1 >= self.height {
+            self.scroll();
+        } else {
+            self.cursor.1 += 1;
+        }
+        self.pending_wrap = false;
+    }
+
+    fn print(&mut self, character: char) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+
+        if self.pending_wrap {
+            self.cursor.0 = 0;
+            self.line_feed();
+        }
+
+        let index = self.index(self.cursor.0, self.cursor.1);
+        self.cells[index] = Cell {
+            character,
+            foreground: self.foreground,
+            background: self.background,
+            attributes: self.attributes
+        };
+
+        if self.cursor.0 + 1 >= self.width {
+            self.pending_wrap = true;
+        } else {
+            self.cursor.0 += 1;
+        }
+    }
+
+    /// Read the colour following a 38 or 48 parameter
+    fn extended_colour(parameters: &mut impl Iterator<Item = u16>) -> Option<Color> {
+        match parameters.next() {
+            Some(5) => Some(indexed_colour(parameters.next().unwrap_or(0) as u8)),
+            Some(2) => {
+                let r = parameters.next().unwrap_or(0) as u8;
+                let g = parameters.next().unwrap_or(0) as u8;
+                let b = parameters.next().unwrap_or(0) as u8;
+                Some(Color::Rgb { r, g, b })
+            },
+            _ => None
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, parameters: &[u16]) {
+        if parameters.is_empty() {
+            self.select_graphic_rendition(&[0]);
+            return;
+        }
+
+        let mut parameters = parameters.iter().copied();
+        while let Some(parameter) = parameters.next() {
+            match parameter {
+                0 => {
+                    self.foreground = Color::Reset;
+                    self.background = Color::Reset;
+                    self.attributes = Attributes::default();
+                },
+                1 => self.attributes.set(Attribute::Bold),
+                2 => self.attributes.set(Attribute::Dim),
+                3 => self.attributes.set(Attribute::Italic),
+                4 => self.attributes.set(Attribute::Underlined),
+                5 => self.attributes.set(Attribute::SlowBlink),
+                6 => self.attributes.set(Attribute::RapidBlink),
+                7 => self.attributes.set(Attribute::Reverse),
+                22 => {
+                    self.attributes.unset(Attribute::Bold);
+                    self.attributes.unset(Attribute::Dim);
+                },
+                23 => self.attributes.unset(Attribute::Italic),
+                24 => self.attributes.unset(Attribute::Underlined),
+                25 => {
+                    self.attributes.unset(Attribute::SlowBlink);
+                    self.attributes.unset(Attribute::RapidBlink);
+                },
+                27 => self.attributes.unset(Attribute::Reverse),
+                30..=37 => self.foreground = indexed_colour((parameter - 30) as u8),
+                90..=97 => self.foreground = indexed_colour((parameter - 90 + 8) as u8),
+                38 => if let Some(colour) = Self::extended_colour(&mut parameters) {
+                    self.foreground = colour;
+                },
+                39 => self.foreground = Color::Reset,
+                40..=47 => self.background = indexed_colour((parameter - 40) as u8),
+                100..=107 => self.background = indexed_colour((parameter - 100 + 8) as u8),
+                48 => if let Some(colour) = Self::extended_colour(&mut parameters) {
+                    self.background = colour;
+                },
+                49 => self.background = Color::Reset,
+                _ => {}
+            }
+        }
+    }
+
+    fn control_sequence(&mut self, parameters: &str, action: char) {
+        // Private sequences such as showing the cursor or the alternate screen don't
+        // change what is on screen
+        if parameters.starts_with('?') {
+            return;
+        }
+
+        let values: Vec<u16> = parameters
+            .split(';')
+            .filter(|value| !value.is_empty())
+            .map(|value| value.parse().unwrap_or(0))
+            .collect();
+        let first = values.first().copied().unwrap_or(0);
+        let count = first.max(1);
+        let (x, y) = self.cursor;
+
+        match action {
+            'H' | 'f' => {
+                let row = first.max(1) - 1;
+                let column = values.get(1).copied().unwrap_or(1).max(1) - 1;
+                self.move_cursor(column, row);
+            },
+            'A' => self.move_cursor(x, y.saturating_sub(count)),
+            'B' => self.move_cursor(x, y.saturating_add(count)),
+            'C' => self.move_cursor(x.saturating_add(count), y),
+            'D' => self.move_cursor(x.saturating_sub(count), y),
+            'E' => self.move_cursor(0, y.saturating_add(count)),
+            'F' => self.move_cursor(0, y.saturating_sub(count)),
+            'G' => self.move_cursor(count - 1, y),
+            'd' => self.move_cursor(x, count - 1),
+            'J' => {
+                let cursor = self.index(x, y);
+                match first {
+                    0 => self.clear_cells(cursor, self.cells.len()),
+                    1 => self.clear_cells(0, cursor + 1),
+                    _ => self.clear_cells(0, self.cells.len())
+                }
+            },
+            'K' => {
+                let line_start = self.index(0, y);
+                let line_end = line_start + self.width as usize;
+                let cursor = self.index(x, y);
+                match first {
+                    0 => self.clear_cells(cursor, line_end),
+                    1 => self.clear_cells(line_start, cursor + 1),
+                    _ => self.clear_cells(line_start, line_end)
+                }
+            },
+            's' => self.saved_cursor = self.cursor,
+            'u' => {
+                let (x, y) = self.saved_cursor;
+                self.move_cursor(x, y);
+            },
+            'm' => self.select_graphic_rendition(&values),
+            _ => {}
+        }
+    }
+
+    fn handle_character(&mut self, character: char) {
+        let state = std::mem::replace(&mut self.state, ParseState::Ground);
+        self.state = match state {
+            ParseState::Ground => match character {
+                '\x1b' => ParseState::Escape,
+                '\r' => {
+                    self.move_cursor(0, self.cursor.1);
+                    ParseState::Ground
+                },
+                '\n' => {
+                    self.line_feed();
+                    ParseState::Ground
+                },
+                '\x08' => {
+                    self.move_cursor(self.cursor.0.saturating_sub(1), self.cursor.1);
+                    ParseState::Ground
+                },
+                character if character.is_control() => ParseState::Ground,
+                character => {
+                    self.print(character);
+                    ParseState::Ground
+                }
+            },
+            ParseState::Escape => match character {
+                '[' => ParseState::Csi(String::new()),
+                '7' => {
+                    self.saved_cursor = self.cursor;
+                    ParseState::Ground
+                },
+                '8' => {
+                    let (x, y) = self.saved_cursor;
+                    self.move_cursor(x, y);
+                    ParseState::Ground
+                },
+                _ => ParseState::Ground
+            },
+            ParseState::Csi(mut parameters) => match character {
+                // The final byte of a control sequence
+                '\x40'..='\x7e' => {
+                    self.control_sequence(&parameters, character);
+                    ParseState::Ground
+                },
+                _ => {
+                    parameters.push(character);
+                    ParseState::Csi(parameters)
+                }
+            }
+        };
+    }
+}
+
+impl Write for VirtualTerminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A character can be split between writes, so hold on to any incomplete bytes
+        let mut bytes = std::mem::take(&mut self.partial_character);
+        bytes.extend_from_slice(buf);
+
+        let mut remaining = bytes.as_slice();
+        while !remaining.is_empty() {
+            match std::str::from_utf8(remaining) {
+                Ok(text) => {
+                    text.chars().for_each(|character| self.handle_character(character));
+                    remaining = &[];
+                },
+                Err(e) => {
+                    let (valid, rest) = remaining.split_at(e.valid_up_to());
+                    std::str::from_utf8(valid).unwrap().chars().for_each(|character| self.handle_character(character));
+                    match e.error_len() {
+                        Some(invalid) => {
+                            self.handle_character(char::REPLACEMENT_CHARACTER);
+                            remaining = &rest[invalid..];
+                        },
+                        None => {
+                            self.partial_character = rest.to_vec();
+                            remaining = &[];
+                        }
+                    }
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flushes += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::{ QueueableCommand, cursor };
+    use crossterm::style::{ Print, SetForegroundColor };
+    use crossterm::terminal::{ Clear, ClearType };
+
+    #[test]
+    fn test_print_and_move() {
+        let mut terminal = VirtualTerminal::new(6, 3);
+        terminal
+            .queue(cursor::MoveTo(1, 1)).unwrap()
+            .queue(SetForegroundColor(Color::Red)).unwrap()
+            .queue(Print("hi")).unwrap()
+            .queue(cursor::MoveToRow(2)).unwrap()
+            .queue(Print("é")).unwrap();
+
+        assert_eq!(terminal.snapshot(), "\n hi\n   é");
+        assert_eq!(terminal.cell(1, 1), Some(&Cell { character: 'h', foreground: Color::Red, ..Cell::default() }));
+        assert_eq!(terminal.cell(0, 0), Some(&Cell::default()));
+        assert_eq!(terminal.cursor(), (4, 2));
+        assert_eq!(terminal.row_text(1).as_deref(), Some(" hi"));
+        assert_eq!(terminal.row_text(3), None);
+    }
+
+    #[test]
+    fn test_clears() {
+        let mut terminal = VirtualTerminal::new(4, 3);
+        terminal.write_all("abcd\r\nefgh\r\nijkl".as_bytes()).unwrap();
+        assert_eq!(terminal.snapshot(), "abcd\nefgh\nijkl");
+
+        terminal
+            .queue(cursor::MoveTo(2, 1)).unwrap()
+            .queue(Clear(ClearType::UntilNewLine)).unwrap();
+        assert_eq!(terminal.snapshot(), "abcd\nef\nijkl");
+
+        terminal
+            .queue(cursor::SavePosition).unwrap()
+            .queue(cursor::MoveTo(1, 0)).unwrap()
+            .queue(Clear(ClearType::FromCursorUp)).unwrap()
+            .queue(cursor::RestorePosition).unwrap();
+        assert_eq!(terminal.snapshot(), "  cd\nef\nijkl");
+        assert_eq!(terminal.cursor(), (2, 1));
+
+        terminal.queue(Clear(ClearType::FromCursorDown)).unwrap();
+        assert_eq!(terminal.snapshot(), "  cd\nef\n");
+
+        terminal.queue(Clear(ClearType::All)).unwrap();
+        assert_eq!(terminal.snapshot(), "\n\n");
+    }
+
+    #[test]
+    fn test_wrapping() {
+        // drawing in the last cell doesn't scroll until something else is printed
+        let mut terminal = VirtualTerminal::new(3, 2);
+        terminal.write_all(b"abcdef").unwrap();
+        assert_eq!(terminal.snapshot(), "abc\ndef");
+
+        terminal.write_all(b"g").unwrap();
+        assert_eq!(terminal.snapshot(), "def\ng");
+    }
+
+    #[test]
+    fn test_split_writes() {
+        let mut terminal = VirtualTerminal::new(4, 1);
+        let bytes = "\x1b[38;2;1;2;3m€".as_bytes();
+        for byte in bytes {
+            terminal.write_all(&[*byte]).unwrap();
+        }
+        assert_eq!(terminal.cell(0, 0), Some(&Cell { character: '€', foreground: Color::Rgb { r: 1, g: 2, b: 3 }, ..Cell::default() }));
+    }
+}
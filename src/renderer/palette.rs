@@ -0,0 +1,124 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::renderer::colours::Colour;
+use serde::{ Serialize, Deserialize };
+use thiserror::Error;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+#[derive(Error, Debug)]
+pub enum PaletteError {
+    #[error("could not access palette file")]
+    Io(#[from] std::io::Error),
+    #[error("palette file is malformed")]
+    Format(#[from] serde_json::Error)
+}
+
+/// A set of named colours, so the look of the game can be changed without touching the code.
+/// In JSON a palette is an object of names to colours, such as `{ "wall": "#555753" }`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Palette {
+    colours: BTreeMap<String, Colour>
+}
+
+impl Palette {
+    pub fn new() -> Palette {
+        Palette::default()
+    }
+
+    /// The 16 named colours every terminal has
+    pub fn standard() -> Palette {
+        let mut palette = Palette::new();
+        for (name, colour) in [
+            ("black", Colour::black()),
+            ("dark_red", Colour::dark_red()),
+            ("dark_green", Colour::dark_green()),
+            ("dark_yellow", Colour::dark_yellow()),
+            ("dark_blue", Colour::dark_blue()),
+            ("dark_purple", Colour::dark_purple()),
+            ("dark_cyan", Colour::dark_cyan()),
+            ("gray", Colour::gray()),
+            ("dark_gray", Colour::dark_gray()),
+            ("red", Colour::red()),
+            ("green", Colour::green()),
+            ("yellow", Colour::yellow()),
+            ("blue", Colour::blue()),
+            ("purple", Colour::purple()),
+            ("cyan", Colour::cyan()),
+            ("white", Colour::white())
+        ] {
+            palette.insert(name, colour);
+        }
+        palette
+    }
+
+    pub fn load<P>(path: P) -> Result<Palette, PaletteError> where
+        P: AsRef<Path> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Colour> {
+        self.colours.get(name).copied()
+    }
+
+    /// The colour with the given name, or the fallback if the palette doesn't have one
+    pub fn get_or(&self, name: &str, fallback: Colour) -> Colour {
+        self.get(name).unwrap_or(fallback)
+    }
+
+    /// Add a colour, replacing any colour already with that name
+    pub fn insert(&mut self, name: &str, colour: Colour) {
+        self.colours.insert(name.to_string(), colour);
+    }
+
+    /// Add every colour from another palette, replacing ours where the names are the same
+    pub fn extend(&mut self, other: &Palette) {
+        self.colours.extend(other.colours.iter().map(|(name, colour)| (name.clone(), *colour)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!("roguelike_palette_{}.json", std::process::id()));
+        std::fs::write(&path, r##"{ "fog": "#00000080", "torch": { "r": 255, "g": 136, "b": 0 }, "red": "#f00" }"##).unwrap();
+
+        let mut palette = Palette::standard();
+        palette.extend(&Palette::load(&path).unwrap());
+        assert_eq!(palette.get("fog"), Some(Colour::rgba(0, 0, 0, 0x80)));
+        assert_eq!(palette.get("torch"), Some(Colour::rgb(0xff, 0x88, 0x00)));
+        assert_eq!(palette.get("red"), Some(Colour::rgb(0xff, 0, 0)));
+        assert_eq!(palette.get("cyan"), Some(Colour::cyan()));
+        assert_eq!(palette.get("mud"), None);
+        assert_eq!(palette.get_or("mud", Colour::dark_yellow()), Colour::dark_yellow());
+
+        std::fs::write(&path, serde_json::to_string(&palette).unwrap()).unwrap();
+        assert_eq!(Palette::load(&path).unwrap(), palette);
+
+        std::fs::write(&path, r#"{ "fog": "grey" }"#).unwrap();
+        assert!(matches!(Palette::load(&path), Err(PaletteError::Format(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
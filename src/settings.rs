@@ -0,0 +1,364 @@
+/*
+    A roguelike game created for a fun exercise
+    Copyright (C) 2023  Bailey Danyluk
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::cli::Options;
+use crate::engine_temp::engine::{ EngineEvent, GameHandler, MAX_SIMULATION_RATE };
+use crate::engine_temp::game::context::EngineContext;
+use crate::engine_temp::plugin::Plugin;
+use crate::renderer::colours::Colour;
+use crate::renderer::crossterm::device_settings::{ ColourMode, DeviceSettings };
+use crate::renderer::palette::{ Palette, PaletteError };
+use serde::{ Serialize, Deserialize };
+use thiserror::Error;
+use std::fs::File;
+use std::io::{ BufReader, BufWriter, Write };
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+
+const MAX_SWAPCHAIN_COUNT: usize = 8;
+const MAX_TICKS_PER_SECOND: f64 = 1000.0;
+/// Any slower and the engine would clamp the rate, so the file wouldn't match the game
+const MIN_TICKS_PER_SECOND: f64 = 1.0 / MAX_SIMULATION_RATE.as_secs_f64();
+
+#[derive(Error, Debug)]
+pub enum SettingsError {
+    #[error("could not access settings file")]
+    Io(#[from] std::io::Error),
+    #[error("settings file is malformed")]
+    Format(#[from] serde_json::Error),
+    #[error("{field} {reason}")]
+    Invalid {
+        field: &'static str,
+        reason: String
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplaySettings {
+    pub swapchain_count: usize,
+    pub width: u16,
+    pub height: u16,
+    pub clear_colour: Colour,
+    pub colour_mode: ColourMode,
+    /// A palette file whose colours replace the standard ones with the same name
+    pub palette: Option<PathBuf>
+}
+
+impl Default for DisplaySettings {
+    fn default() -> DisplaySettings {
+        DisplaySettings {
+            swapchain_count: 2,
+            width: 80,
+            height: 24,
+            clear_colour: Colour::black(),
+            colour_mode: ColourMode::default(),
+            palette: None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationSettings {
+    /// How many fixed updates are ran a second
+    pub ticks_per_second: f64,
+    /// The most fixed updates ran in one frame before the game starts to slow down
+    pub max_fixed_steps: u32
+}
+
+impl Default for SimulationSettings {
+    fn default() -> SimulationSettings {
+        SimulationSettings {
+            ticks_per_second: 60.0,
+            max_fixed_steps: 8
+        }
+    }
+}
+
+impl SimulationSettings {
+    pub fn simulation_rate(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.ticks_per_second)
+    }
+}
+
+/// Options for the engine and renderer that are kept between runs. Anything missing
+/// from the file is given its default
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub display: DisplaySettings,
+    pub simulation: SimulationSettings
+}
+
+impl Settings {
+    pub fn load<P>(path: P) -> Result<Settings, SettingsError> where
+        P: AsRef<Path> {
+        let reader = BufReader::new(File::open(path)?);
+        let settings: Settings = serde_json::from_reader(reader)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Load settings from a file, or use the defaults if it doesn't exist yet. The file is
+    /// only created once a setting is changed in game
+    pub fn load_or_default<P>(path: P) -> Result<Settings, SettingsError> where
+        P: AsRef<Path> {
+        if path.as_ref().exists() {
+            return Settings::load(path);
+        }
+        Ok(Settings::default())
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<(), SettingsError> where
+        P: AsRef<Path> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let display = &self.display;
+        if display.swapchain_count == 0 || display.swapchain_count > MAX_SWAPCHAIN_COUNT {
+            return Err(SettingsError::Invalid {
+                field: "display.swapchain_count",
+                reason: format!("must be between 1 and {}", MAX_SWAPCHAIN_COUNT)
+            });
+        }
+
+        if display.width == 0 || display.height == 0 {
+            return Err(SettingsError::Invalid {
+                field: "display size",
+                reason: format!("must not be zero, got {}x{}", display.width, display.height)
+            });
+        }
+
+        let ticks_per_second = self.simulation.ticks_per_second;
+        if !(MIN_TICKS_PER_SECOND..=MAX_TICKS_PER_SECOND).contains(&ticks_per_second) {
+            return Err(SettingsError::Invalid {
+                field: "simulation.ticks_per_second",
+                reason: format!("must be between {} and {}", MIN_TICKS_PER_SECOND, MAX_TICKS_PER_SECOND)
+            });
+        }
+
+        if self.simulation.max_fixed_steps == 0 {
+            return Err(SettingsError::Invalid {
+                field: "simulation.max_fixed_steps",
+                reason: "must be at least 1".to_string()
+            });
+        }
+        Ok(())
+    }
+
+    /// Apply the options given on the command line on top of the file's settings
+    pub fn merge_options(&mut self, options: &Options) {
+        if let Some(colour_mode) = options.colour_mode {
+            self.display.colour_mode = colour_mode;
+        }
+    }
+
+    /// The renderer's settings. An automatic colour mode is passed on as it is, so it should
+    /// be resolved once at startup beforehand
+    pub fn device_settings(&self) -> DeviceSettings {
+        DeviceSettings {
+            swapchain_count: self.display.swapchain_count,
+            display_length_x: self.display.width,
+            display_length_y: self.display.height,
+            clear_colour: self.display.clear_colour,
+            colour_mode: self.display.colour_mode
+        }
+    }
+
+    /// The standard palette with the settings' palette file, if any, loaded over it
+    pub fn load_palette(&self) -> Result<Palette, PaletteError> {
+        let mut palette = Palette::standard();
+        if let Some(path) = &self.display.palette {
+            palette.extend(&Palette::load(path)?);
+        }
+        Ok(palette)
+    }
+
+    pub fn apply_simulation(&self, game_handler: &mut GameHandler) {
+        game_handler.set_simulation_rate(self.simulation.simulation_rate());
+        game_handler.set_max_fixed_steps(self.simulation.max_fixed_steps);
+    }
+}
+
+/// Writes settings back to their file when they are changed in game. Only the settings
+/// as they were loaded from the file are written, so command line overrides don't stick
+pub struct SettingsWriter {
+    path: PathBuf,
+    settings: Settings
+}
+
+impl SettingsWriter {
+    pub fn new<P>(path: P, settings: Settings) -> SettingsWriter where
+        P: Into<PathBuf> {
+        SettingsWriter {
+            path: path.into(),
+            settings
+        }
+    }
+
+    /// The settings after an engine event, if it changes any of them
+    fn apply(&self, event: &EngineEvent) -> Option<Settings> {
+        let mut settings = self.settings.clone();
+        if let EngineEvent::SetSimulationRate(simulation_rate) = event {
+            settings.simulation.ticks_per_second = 1.0 / simulation_rate.as_secs_f64();
+        }
+        Some(settings).filter(|settings| *settings != self.settings)
+    }
+}
+
+impl Plugin for SettingsWriter {
+    fn name(&self) -> &str {
+        "settings_writer"
+    }
+
+    fn on_engine_event(&mut self, _ctx: &EngineContext, event: &EngineEvent) {
+        let Some(settings) = self.apply(event) else {
+            return;
+        };
+
+        // Invalid settings are never kept, so a bad change can't end up written later on
+        if let Err(e) = settings.validate() {
+            log::error!("Ignoring invalid settings change: {}", e);
+            return;
+        }
+        self.settings = settings;
+        if let Err(e) = self.settings.save(&self.path) {
+            log::error!("Could not write settings to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine_temp::engine::EngineEventHandler;
+    use crate::engine_temp::input::keymap::{ Keymap, KeymapPreset };
+    use crate::engine_temp::profiler::Profiler;
+    use crate::engine_temp::game::context::Time;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("roguelike_settings_{}_{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = temp_path("round_trip");
+        let mut settings = Settings::default();
+        settings.display.width = 120;
+        settings.display.colour_mode = ColourMode::TrueColour;
+        settings.save(&path).unwrap();
+
+        assert_eq!(Settings::load(&path).unwrap(), settings);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_partial_and_invalid() {
+        let settings: Settings = serde_json::from_str(r#"{ "display": { "height": 40 } }"#).unwrap();
+        assert_eq!(settings.display.height, 40);
+        assert_eq!(settings.display.width, 80);
+        assert_eq!(settings.simulation, SimulationSettings::default());
+
+        let mut settings = Settings::default();
+        settings.display.swapchain_count = 0;
+        assert!(matches!(settings.validate(), Err(SettingsError::Invalid { field: "display.swapchain_count", .. })));
+
+        let mut settings = Settings::default();
+        settings.simulation.ticks_per_second = f64::NAN;
+        assert!(matches!(settings.validate(), Err(SettingsError::Invalid { field: "simulation.ticks_per_second", .. })));
+
+        let path = temp_path("invalid");
+        std::fs::write(&path, r#"{ "display": { "width": 0 } }"#).unwrap();
+        assert!(matches!(Settings::load(&path), Err(SettingsError::Invalid { .. })));
+        std::fs::write(&path, "{ display").unwrap();
+        assert!(matches!(Settings::load(&path), Err(SettingsError::Format(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_merge_options() {
+        let mut settings = Settings::default();
+        settings.merge_options(&Options::default());
+        assert_eq!(settings, Settings::default());
+
+        settings.merge_options(&Options { colour_mode: Some(ColourMode::TrueColour), ..Options::default() });
+        assert_eq!(settings.device_settings().colour_mode, ColourMode::TrueColour);
+    }
+
+    #[test]
+    fn test_load_palette() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.load_palette().unwrap(), Palette::standard());
+
+        let path = temp_path("palette");
+        std::fs::write(&path, r##"{ "menu_text": "#ffd700", "red": "#800000" }"##).unwrap();
+        settings.display.palette = Some(path.clone());
+        let palette = settings.load_palette().unwrap();
+        assert_eq!(palette.get("menu_text"), Some(Colour::rgb(0xff, 0xd7, 0x00)));
+        assert_eq!(palette.get("red"), Some(Colour::rgb(0x80, 0, 0)));
+        assert_eq!(palette.get("cyan"), Some(Colour::cyan()));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(settings.load_palette(), Err(PaletteError::Io(_))));
+    }
+
+    #[test]
+    fn test_write_back() {
+        let path = temp_path("write_back");
+        let events = EngineEventHandler::new();
+        let publisher = events.get_publisher();
+        let profiler = Profiler::new(1);
+        let keymap = Keymap::preset(KeymapPreset::ViKeys);
+        let context = EngineContext {
+            events: &publisher,
+            keymap: &keymap,
+            inputs: &[],
+            seed: 0,
+            profiler: &profiler,
+            palette: &Palette::standard(),
+            time: Time::default()
+        };
+
+        let mut writer = SettingsWriter::new(&path, Settings::default());
+        writer.on_engine_event(&context, &EngineEvent::Pause);
+        assert!(!path.exists());
+
+        // a change that fails validation is neither written nor kept, including a rate
+        // slower than the engine would run at
+        writer.on_engine_event(&context, &EngineEvent::SetSimulationRate(Duration::from_nanos(1)));
+        writer.on_engine_event(&context, &EngineEvent::SetSimulationRate(Duration::from_secs(2)));
+        assert!(!path.exists());
+        assert_eq!(writer.settings, Settings::default());
+
+        writer.on_engine_event(&context, &EngineEvent::SetSimulationRate(Duration::from_millis(50)));
+        assert_eq!(Settings::load(&path).unwrap().simulation.ticks_per_second, 20.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_or_default() {
+        let path = temp_path("load_or_default");
+        assert_eq!(Settings::load_or_default(&path).unwrap(), Settings::default());
+        assert!(!path.exists());
+    }
+}